@@ -6,13 +6,16 @@ use std::{
     process::{Command, Stdio},
 };
 
+use anchor_lang::solana_program::rent::Rent;
 use anyhow::{Context, Result};
 use convert_case::{Case, Casing};
 use regex::{Regex, RegexBuilder};
-use swc_ecma_ast::Module;
+use swc_ecma_ast::{Decl, Module, ModuleDecl, ModuleItem};
 use toml::Value;
 
+use crate::helpers::extract_type::inline_constant_lengths;
 use crate::parse_ts::parse_ts;
+use crate::rs_types::{ProgramAccount, ProgramAccountField};
 use crate::transpiler::transpile;
 
 pub fn init(name: &String) {
@@ -103,7 +106,7 @@ pub fn init(name: &String) {
     );
 }
 
-pub fn build_workspace() -> Result<()> {
+pub fn build_workspace(init_space: bool) -> Result<()> {
     // Verify we're in a workspace root
     if !Path::new("Anchor.toml").exists() {
         return Err(anyhow::anyhow!(
@@ -157,8 +160,13 @@ pub fn build_workspace() -> Result<()> {
         let rs_file = src_dir.join("lib.rs");
         println!("Compiling {} to {}", ts_file.display(), rs_file.display());
 
-        let module: Module = parse_ts(&ts_file.to_string_lossy().to_string());
-        transpile(&module, &rs_file.to_string_lossy().to_string())?;
+        let (module, cm) = parse_ts(&ts_file.to_string_lossy().to_string());
+        transpile(
+            &module,
+            &cm,
+            &rs_file.to_string_lossy().to_string(),
+            init_space,
+        )?;
 
         println!("Successfully compiled {}", program_name);
     }
@@ -167,7 +175,7 @@ pub fn build_workspace() -> Result<()> {
     Ok(())
 }
 
-pub fn run_tests() -> Result<()> {
+pub fn run_tests(init_space: bool) -> Result<()> {
     // Verify we're in a workspace root by checking for Anchor.toml
     if !Path::new("Anchor.toml").exists() {
         return Err(anyhow::anyhow!(
@@ -178,7 +186,7 @@ pub fn run_tests() -> Result<()> {
     println!("Running anchor tests...");
 
     // Build the workspace first
-    build_workspace()?;
+    build_workspace(init_space)?;
 
     // Execute anchor test
     let mut cmd = Command::new("anchor");
@@ -244,6 +252,127 @@ pub fn sync_program_ids() -> Result<()> {
     Ok(())
 }
 
+/// A row of an account layout: name, type, offset, max offset, size and max size.
+type LayoutRow = (String, String, u32, u32, u32, u32);
+
+pub fn print_layout(input: &String, json: bool) -> Result<()> {
+    let (mut module, _) = parse_ts(input);
+    let layouts = account_layouts(&mut module);
+
+    if json {
+        let accounts_json: Vec<serde_json::Value> = layouts
+            .iter()
+            .map(|(account, rows, rent_exempt)| {
+                let fields: Vec<serde_json::Value> = rows
+                    .iter()
+                    .map(|(name, of_type, offset, max_offset, size, max_size)| {
+                        serde_json::json!({
+                            "name": name,
+                            "type": of_type,
+                            "offset": offset,
+                            "max_offset": max_offset,
+                            "size": size,
+                            "max_size": max_size,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "name": account.name,
+                    "space": account.space,
+                    "rent_exempt_lamports": rent_exempt,
+                    "fields": fields,
+                })
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({ "accounts": accounts_json }))?
+        );
+        return Ok(());
+    }
+
+    for (account, rows, rent_exempt) in layouts {
+        println!(
+            "{} (space: {} bytes, rent-exempt: {} lamports)",
+            account.name, account.space, rent_exempt
+        );
+        println!(
+            "  {:>10}  {:>6}  {:>8}  {:<24}  type",
+            "offset", "size", "max_size", "field"
+        );
+        for (name, of_type, offset, max_offset, size, max_size) in rows {
+            // fields after a Str/Vec move with its length
+            let offset = match offset == max_offset {
+                true => offset.to_string(),
+                false => format!("{}..{}", offset, max_offset),
+            };
+            println!(
+                "  {:>10}  {:>6}  {:>8}  {:<24}  {}",
+                offset, size, max_size, name, of_type
+            );
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// The accounts declared in `module`, each with the rows of its layout and its rent-exempt
+/// balance.
+fn account_layouts(module: &mut Module) -> Vec<(ProgramAccount, Vec<LayoutRow>, u64)> {
+    inline_constant_lengths(module);
+    let accounts: Vec<ProgramAccount> = module
+        .body
+        .iter()
+        .filter_map(|item| match item {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export_decl)) => {
+                match &export_decl.decl {
+                    Decl::TsInterface(interface) if !interface.extends.is_empty() => {
+                        Some(ProgramAccount::from_ts_expr(*interface.clone()))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+        .collect();
+
+    let rent = Rent::default();
+    let mut layouts = vec![];
+    for account in accounts {
+        // starting with the anchor discriminator
+        let mut rows = vec![("discriminator".to_string(), "[u8; 8]".to_string(), 0, 0, 8, 8)];
+        let (mut offset, mut max_offset) = (8, 8);
+        for field in &account.fields {
+            let (size, max_size) = (field.min_space(), field.max_space());
+            rows.push((
+                field.name.to_case(Case::Snake),
+                ts_type_with_len(field),
+                offset,
+                max_offset,
+                size,
+                max_size,
+            ));
+            offset += size;
+            max_offset += max_size;
+        }
+        let rent_exempt = rent.minimum_balance(account.space as usize);
+        layouts.push((account, rows, rent_exempt));
+    }
+    layouts
+}
+
+fn ts_type_with_len(field: &ProgramAccountField) -> String {
+    match (field.of_type.as_str(), field.max_len.as_slice()) {
+        ("Str", [len]) => format!("Str<{}>", len),
+        ("Vec<Str>", [vec_len, str_len]) => format!("Vec<Str<{}>, {}>", str_len, vec_len),
+        (of_type, [vec_len]) if of_type.starts_with("Vec<") || of_type.starts_with("Array<") => {
+            format!("{}, {}>", of_type.trim_end_matches('>'), vec_len)
+        }
+        (of_type, _) => of_type.to_string(),
+    }
+}
+
 fn extract_program_ids(anchor_toml: &str) -> Result<HashMap<String, String>> {
     let toml_value: Value = anchor_toml.parse().context("Failed to parse Anchor.toml")?;
 
@@ -365,3 +494,41 @@ export default class {} {{
         program_id
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transpiler::parse_source;
+
+    #[test]
+    fn layout_offsets_and_sizes() {
+        let (mut module, _) = parse_source(
+            r#"export interface Profile extends Account {
+              owner: Pubkey
+              name: Str<32>
+              scores: Vec<u64, 5>
+              bump: u8
+            }"#,
+        )
+        .unwrap();
+        let layouts = account_layouts(&mut module);
+        assert_eq!(layouts.len(), 1);
+        let (account, rows, rent_exempt) = &layouts[0];
+        assert_eq!(account.name, "Profile");
+        assert_eq!(account.space, 121);
+        assert_eq!(*rent_exempt, 1_733_040);
+        let row = |name: &str, of_type: &str, offset, max_offset, size, max_size| {
+            (name.to_string(), of_type.to_string(), offset, max_offset, size, max_size)
+        };
+        assert_eq!(
+            rows,
+            &vec![
+                row("discriminator", "[u8; 8]", 0, 0, 8, 8),
+                row("owner", "Pubkey", 8, 8, 32, 32),
+                row("name", "Str<32>", 40, 40, 4, 36),
+                row("scores", "Vec<u64, 5>", 44, 76, 4, 44),
+                row("bump", "u8", 48, 120, 1, 1),
+            ]
+        );
+    }
+}
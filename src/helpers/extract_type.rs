@@ -1,4 +1,8 @@
-use swc_ecma_ast::{TsType, TsTypeParamInstantiation};
+use std::collections::HashMap;
+use swc_ecma_ast::{
+    ClassMember, DefaultDecl, Decl, Expr, Lit, Module, ModuleDecl, ModuleItem, Number, TsEntityName,
+    TsLit, TsLitType, TsType, TsTypeElement, TsTypeParamInstantiation, TsTypeQueryExpr,
+};
 
 use crate::errors::PoseidonError;
 use anyhow::{Error, Ok, Result};
@@ -115,6 +119,25 @@ fn extract_name_and_len_with_type_params(
                 ts_type = format!("Vec<{}>", vec_type_name);
             }
         }
+        "Array" => {
+            let array_type_name = type_params.params[0]
+                .as_ts_type_ref()
+                .ok_or(PoseidonError::TypeReferenceNotFound)?
+                .type_name
+                .as_ident()
+                .ok_or(PoseidonError::IdentNotFound)?
+                .sym
+                .to_string();
+
+            length += type_params.params[1]
+                .as_ts_lit_type()
+                .ok_or(PoseidonError::TSLiteralTypeNotFound)?
+                .lit
+                .as_number()
+                .ok_or(PoseidonError::NumericLiteralNotFound)?
+                .value as u32;
+            ts_type = format!("Array<{}>", array_type_name);
+        }
         _ => {
             return Err(
                 PoseidonError::KeyWordTypeNotSupported(format!("{:?}", primary_type_ident)).into(),
@@ -124,3 +147,137 @@ fn extract_name_and_len_with_type_params(
 
     Ok((ts_type, length))
 }
+
+/// Returns the `max_len` arguments of a dynamically sized type, outermost first,
+/// e.g. `Str<10>` -> `[10]` and `Vec<Str<10>, 5>` -> `[5, 10]`.
+/// Fixed-size `Array<T, N>` types report their length the same way.
+pub fn extract_max_len(binding: &swc_ecma_ast::TsTypeAnn) -> Result<Vec<u32>, Error> {
+    max_len_from_ts_type(binding.type_ann.as_ref())
+}
+
+fn max_len_from_ts_type(ts_type: &TsType) -> Result<Vec<u32>, Error> {
+    let type_ref = match ts_type.as_ts_type_ref() {
+        Some(type_ref) => type_ref,
+        None => return Ok(vec![]),
+    };
+    let type_name = type_ref
+        .type_name
+        .as_ident()
+        .ok_or(PoseidonError::IdentNotFound)?
+        .sym
+        .as_ref();
+    let type_params = match &type_ref.type_params {
+        Some(type_params) => type_params,
+        None => return Ok(vec![]),
+    };
+    let len_param = match type_name {
+        "Str" => 0,
+        "Vec" | "Array" => 1,
+        _ => return Ok(vec![]),
+    };
+    let mut max_len = vec![type_params
+        .params
+        .get(len_param)
+        .ok_or(PoseidonError::TSLiteralTypeNotFound)?
+        .as_ts_lit_type()
+        .ok_or(PoseidonError::TSLiteralTypeNotFound)?
+        .lit
+        .as_number()
+        .ok_or(PoseidonError::NumericLiteralNotFound)?
+        .value as u32];
+    if type_name != "Str" {
+        max_len.extend(max_len_from_ts_type(&type_params.params[0])?);
+    }
+    Ok(max_len)
+}
+
+/// Replaces `typeof Program.NAME` type arguments with the value of the numeric constant
+/// `static readonly NAME = N` of the program class, so `Str<typeof Program.MAX_LEN>` is read
+/// like `Str<32>` in account fields and instruction parameters.
+pub fn inline_constant_lengths(module: &mut Module) {
+    let mut constants: HashMap<String, f64> = HashMap::new();
+    for item in module.body.iter() {
+        if let ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) = item {
+            if let DefaultDecl::Class(class) = &export.decl {
+                for member in class.class.body.iter() {
+                    if let ClassMember::ClassProp(prop) = member {
+                        if let (Some(key), Some(Expr::Lit(Lit::Num(num)))) =
+                            (prop.key.as_ident(), prop.value.as_deref())
+                        {
+                            constants.insert(key.sym.to_string(), num.value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if constants.is_empty() {
+        return;
+    }
+    for item in module.body.iter_mut() {
+        match item {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => {
+                if let Decl::TsInterface(interface) = &mut export.decl {
+                    for element in interface.body.body.iter_mut() {
+                        if let TsTypeElement::TsPropertySignature(property) = element {
+                            if let Some(type_ann) = property.type_ann.as_mut() {
+                                inline_lengths_in_ts_type(&mut type_ann.type_ann, &constants);
+                            }
+                        }
+                    }
+                }
+            }
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(export)) => {
+                if let DefaultDecl::Class(class) = &mut export.decl {
+                    for member in class.class.body.iter_mut() {
+                        if let ClassMember::Method(method) = member {
+                            for param in method.function.params.iter_mut() {
+                                if let Some(type_ann) =
+                                    param.pat.as_mut_ident().and_then(|b| b.type_ann.as_mut())
+                                {
+                                    inline_lengths_in_ts_type(&mut type_ann.type_ann, &constants);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn inline_lengths_in_ts_type(ts_type: &mut TsType, constants: &HashMap<String, f64>) {
+    let type_params = match ts_type {
+        TsType::TsTypeRef(type_ref) => match type_ref.type_params.as_mut() {
+            Some(type_params) => type_params,
+            None => return,
+        },
+        _ => return,
+    };
+    for param in type_params.params.iter_mut() {
+        let value = match param.as_ref() {
+            TsType::TsTypeQuery(query) => match &query.expr_name {
+                TsTypeQueryExpr::TsEntityName(TsEntityName::TsQualifiedName(name)) => {
+                    constants.get(name.right.sym.as_ref()).copied()
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                let span = param.as_ts_type_query().map(|q| q.span).unwrap_or_default();
+                **param = TsType::TsLitType(TsLitType {
+                    span,
+                    lit: TsLit::Number(Number {
+                        span,
+                        value,
+                        raw: None,
+                    }),
+                });
+            }
+            None => inline_lengths_in_ts_type(param, constants),
+        }
+    }
+}
@@ -1,79 +1,222 @@
 use anyhow::{anyhow, Result};
-use regex::Regex;
+use proc_macro2::{Delimiter, Group, Spacing, TokenStream, TokenTree};
+use quote::quote;
+use rust_format::{Formatter, PrettyPlease};
 
-pub fn extract_accounts_structs(input: &str) -> Vec<String> {
-    // Regex to capture structs with #[derive(Accounts)]
-    let regex = Regex::new(
-        r"(?s)#\[derive\(Accounts\)\](?:\s*#\[[^\]]*\])?\s*pub struct (\w+<'?\w*>) \{(.*?)\}",
-    )
-    .unwrap();
+// same line width prettyplease wraps the rest of the program at
+const MARGIN: usize = 89;
+const INDENT: &str = "    ";
+// doc comment standing in for an attribute list while prettyplease prints the struct
+const PLACEHOLDER: &str = "///poseidon-attribute-";
 
-    regex
-        .captures_iter(input)
-        .map(|cap| format!("pub struct {} {{\n{}\n}}", &cap[1], &cap[2]))
-        .collect()
+/// Formats a `#[derive(Accounts)]` struct. prettyplease prints attribute arguments as raw
+/// tokens, splitting `==` and `seed: u64` and breaking long lists inside empty parentheses,
+/// so the `#[account(...)]` and `#[instruction(...)]` lists are printed here and prettyplease
+/// prints the rest.
+pub fn format_accounts_struct(tokens: TokenStream) -> Result<String> {
+    let mut attributes = vec![];
+    let tokens = take_attributes(tokens, &mut attributes);
+    let formatted = PrettyPlease::default().format_str(tokens.to_string())?;
+    let mut out = String::new();
+    for line in formatted.lines() {
+        let code = line.trim_start();
+        let indent = &line[..line.len() - code.len()];
+        match code
+            .strip_prefix(PLACEHOLDER)
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(n) => out.push_str(&format_attribute(&attributes[n], indent)?),
+            None => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
 }
 
-pub fn reorder_struct(input: &str) -> Result<(String, String)> {
-    let field_regex = Regex::new(
-        r"(?ms)^(?P<attrs>(\s*#\[[^\]]*\](\s*|.*?))*?)\s*pub\s+(?P<name>\w+):\s+(?P<type>[^\n]+),",
-    )
-    .unwrap();
-
-    let mut init_fields = Vec::new();
-    let mut init_if_needed_fields = Vec::new();
-    let mut other_fields = Vec::new();
-
-    for cap in field_regex.captures_iter(input) {
-        let attrs = cap.name("attrs").unwrap().as_str().trim();
-
-        let field = format!(
-            "{}\n    pub {}: {},",
-            attrs,
-            cap.name("name").unwrap().as_str(),
-            cap.name("type").unwrap().as_str()
-        );
-
-        if attrs.contains("init") && !attrs.contains("init_if_needed") {
-            init_fields.push(field);
-        } else if attrs.contains("init_if_needed") {
-            init_if_needed_fields.push(field);
-        } else {
-            other_fields.push(field);
-        }
+/// Swaps the `#[account(...)]` and `#[instruction(...)]` attributes of the struct and its
+/// fields for placeholder doc comments, collecting their names and arguments.
+fn take_attributes(
+    tokens: TokenStream,
+    attributes: &mut Vec<(String, TokenStream)>,
+) -> TokenStream {
+    let mut out = TokenStream::new();
+    for token in tokens {
+        let token = match token {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket => {
+                let mut inner = group.stream().into_iter();
+                match (inner.next(), inner.next(), inner.next()) {
+                    (Some(TokenTree::Ident(name)), Some(TokenTree::Group(args)), None)
+                        if (name == "account" || name == "instruction")
+                            && args.delimiter() == Delimiter::Parenthesis =>
+                    {
+                        let placeholder = format!("{}{}", &PLACEHOLDER[3..], attributes.len());
+                        attributes.push((name.to_string(), args.stream()));
+                        TokenTree::Group(Group::new(
+                            Delimiter::Bracket,
+                            quote! { doc = #placeholder },
+                        ))
+                    }
+                    _ => TokenTree::Group(group),
+                }
+            }
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                TokenTree::Group(Group::new(
+                    Delimiter::Brace,
+                    take_attributes(group.stream(), attributes),
+                ))
+            }
+            token => token,
+        };
+        out.extend([token]);
     }
+    out
+}
 
-    let mut reordered_fields = String::new();
-    for field in init_fields {
-        reordered_fields.push_str(&field);
-        reordered_fields.push('\n');
+/// Prints an attribute list on one line when it fits, otherwise one argument per line.
+fn format_attribute((name, args): &(String, TokenStream), indent: &str) -> Result<String> {
+    let items = split_top_level(args.clone())
+        .into_iter()
+        .map(|item| format_item(item, indent.len() + INDENT.len()))
+        .collect::<Result<Vec<String>>>()?;
+    let line = format!("{}#[{}({})]", indent, name, items.join(", "));
+    if line.len() <= MARGIN && !line.contains('\n') {
+        return Ok(line + "\n");
     }
-    for field in init_if_needed_fields {
-        reordered_fields.push_str(&field);
-        reordered_fields.push('\n');
+    let mut out = format!("{}#[{}(\n", indent, name);
+    for item in items {
+        let item = item.replace('\n', &format!("\n{}{}", indent, INDENT));
+        out.push_str(&format!("{}{}{},\n", indent, INDENT, item));
     }
-    for field in other_fields {
-        reordered_fields.push_str(&field);
-        reordered_fields.push('\n');
+    out.push_str(&format!("{})]\n", indent));
+    Ok(out)
+}
+
+/// Prints a flag like `mut`, a `key = value` constraint with an optional `@ Error`, or an
+/// instruction argument `name: Type`, leaving the value or type to prettyplease. `column` is
+/// where the argument starts when the list is broken over several lines.
+fn format_item(item: Vec<TokenTree>, column: usize) -> Result<String> {
+    let follows_joint = |i: usize| {
+        i > 0 && matches!(&item[i - 1], TokenTree::Punct(p) if p.spacing() == Spacing::Joint)
+    };
+    // the first `=` or `:` that isn't part of `::`, `==`, `<=` and the like
+    let separator = item.iter().enumerate().position(|(i, token)| match token {
+        TokenTree::Punct(p) => {
+            (p.as_char() == '=' || p.as_char() == ':')
+                && p.spacing() == Spacing::Alone
+                && !follows_joint(i)
+        }
+        _ => false,
+    });
+    let print = |tokens: &[TokenTree]| {
+        TokenStream::from_iter(tokens.iter().cloned())
+            .to_string()
+            .replace(' ', "")
+    };
+    let Some(i) = separator else {
+        return Ok(print(&item));
+    };
+    let key = print(&item[..i]);
+    let value = &item[i + 1..];
+    if matches!(&item[i], TokenTree::Punct(p) if p.as_char() == ':') {
+        return Ok(format!("{}: {}", key, pretty("type T = ", value)?));
     }
+    let (value, error) = match value
+        .iter()
+        .position(|t| matches!(t, TokenTree::Punct(p) if p.as_char() == '@'))
+    {
+        Some(at) => (&value[..at], format!(" @ {}", print(&value[at + 1..]))),
+        None => (value, String::new()),
+    };
+    // pad the constant's name so prettyplease wraps the value where it will sit
+    let name = "_".repeat((column + key.len() + 3).saturating_sub(14).max(1));
+    let value = pretty(&format!("const {}: () = ", name), value)?;
+    Ok(format!("{} = {}{}", key, value, error))
+}
 
-    let struct_regex = Regex::new(r"(?ms)^pub\s+struct\s+\w+<'\w+>\s*\{").unwrap();
-    if let Some(header) = struct_regex.find(input) {
-        Ok((
-            header.as_str().to_string(),
-            format!("{}\n{}\n}}", header.as_str(), reordered_fields),
-        ))
-    } else {
-        Err(anyhow!("Invalid struct input"))
+/// Prints `tokens` with prettyplease as the right-hand side of an item starting with `prefix`.
+fn pretty(prefix: &str, tokens: &[TokenTree]) -> Result<String> {
+    let tokens = TokenStream::from_iter(tokens.iter().cloned());
+    let formatted = PrettyPlease::default().format_str(format!("{}{};", prefix, tokens))?;
+    formatted
+        .trim_end()
+        .strip_prefix(prefix)
+        .and_then(|f| f.strip_suffix(';'))
+        .map(String::from)
+        .ok_or(anyhow!("could not format `{}`", tokens))
+}
+
+/// Splits attribute arguments on their top-level commas, dropping a trailing one.
+fn split_top_level(tokens: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut items = vec![vec![]];
+    for token in tokens {
+        if matches!(&token, TokenTree::Punct(p) if p.as_char() == ',') {
+            items.push(vec![]);
+        } else if let Some(item) = items.last_mut() {
+            item.push(token);
+        }
     }
+    items.retain(|item| !item.is_empty());
+    items
 }
 
-pub fn replace_struct(code: &str, struct_header: &str, new_struct: &str) -> String {
-    let struct_regex = Regex::new(&format!(
-        r"(?ms)^{}.*?(}})",
-        regex::escape(struct_header.trim())
-    ))
-    .unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    struct_regex.replace(code, new_struct).to_string()
+    #[test]
+    fn nested_generics() {
+        let rs = format_accounts_struct(quote! {
+            #[derive(Accounts)]
+            #[instruction(seed: u64, hobbies: Vec<Vec<u8>>)]
+            pub struct MakeContext<'info> {
+                #[account(mint::token_program = token_program)]
+                pub mint: Option<Box<InterfaceAccount<'info, token_interface::Mint>>>,
+                #[account(mut, seeds = [b"vault", seed.to_le_bytes().as_ref()], bump)]
+                pub vault: AccountLoader<'info, Vault>,
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            rs,
+            "#[derive(Accounts)]\n\
+             #[instruction(seed: u64, hobbies: Vec<Vec<u8>>)]\n\
+             pub struct MakeContext<'info> {\n    \
+                 #[account(mint::token_program = token_program)]\n    \
+                 pub mint: Option<Box<InterfaceAccount<'info, token_interface::Mint>>>,\n    \
+                 #[account(mut, seeds = [b\"vault\", seed.to_le_bytes().as_ref()], bump)]\n    \
+                 pub vault: AccountLoader<'info, Vault>,\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn long_constraint_expressions() {
+        let rs = format_accounts_struct(quote! {
+            #[derive(Accounts)]
+            pub struct TakeContext<'info> {
+                #[account(
+                    mut,
+                    has_one = maker @ EscrowError::InvalidMaker,
+                    constraint = escrow.receive_amount >= 1_000 && escrow.maker != taker.key() && !escrow.locked @ EscrowError::Locked,
+                )]
+                pub escrow: Account<'info, Escrow>,
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            rs,
+            "#[derive(Accounts)]\n\
+             pub struct TakeContext<'info> {\n    \
+                 #[account(\n        \
+                     mut,\n        \
+                     has_one = maker @ EscrowError::InvalidMaker,\n        \
+                     constraint = escrow.receive_amount >= 1_000 && escrow.maker != taker.key()\n            \
+                         && !escrow.locked @ EscrowError::Locked,\n    \
+                 )]\n    \
+                 pub escrow: Account<'info, Escrow>,\n\
+             }\n"
+        );
+    }
 }
@@ -5,13 +5,13 @@ mod parse_ts;
 mod rs_types;
 mod transpiler;
 mod ts_types;
+mod type_checker;
 
 use anyhow::Result;
 use clap::{Parser as ClapParser, Subcommand};
 use parse_ts::parse_ts;
-use swc_ecma_ast::Module;
 
-use cli::{build_workspace, init, run_tests, sync_program_ids};
+use cli::{build_workspace, init, print_layout, run_tests, sync_program_ids};
 use transpiler::transpile;
 
 #[derive(ClapParser, Debug)]
@@ -25,9 +25,17 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Build Typescript programs in workspace
-    Build,
+    Build {
+        /// Size accounts with Anchor's InitSpace derive
+        #[arg(long, help = "Size accounts with #[derive(InitSpace)]")]
+        init_space: bool,
+    },
     /// Run anchor tests in the workspace
-    Test,
+    Test {
+        /// Size accounts with Anchor's InitSpace derive
+        #[arg(long, help = "Size accounts with #[derive(InitSpace)]")]
+        init_space: bool,
+    },
     /// Sync anchor keys in poseidon programs
     Sync,
     /// Transpile a Typescript program to a Rust program
@@ -38,6 +46,17 @@ enum Commands {
         /// Output Rust file path
         #[arg(short, long, help = "Output Rust file")]
         output: String,
+        /// Size accounts with Anchor's InitSpace derive
+        #[arg(long, help = "Size accounts with #[derive(InitSpace)]")]
+        init_space: bool,
+    },
+    /// Print the Borsh layout and rent of every account in a Typescript program
+    Layout {
+        /// Input Typescript file path
+        input: String,
+        /// Print the layout as JSON
+        #[arg(long, help = "Print the layout as JSON")]
+        json: bool,
     },
     /// Initializes a new workspace
     Init {
@@ -53,15 +72,22 @@ fn main() -> Result<()> {
         Commands::Sync => {
             sync_program_ids()?;
         }
-        Commands::Test => {
-            run_tests()?;
+        Commands::Test { init_space } => {
+            run_tests(*init_space)?;
+        }
+        Commands::Build { init_space } => {
+            build_workspace(*init_space)?;
         }
-        Commands::Build => {
-            build_workspace()?;
+        Commands::Compile {
+            input,
+            output,
+            init_space,
+        } => {
+            let (module, cm) = parse_ts(input);
+            transpile(&module, &cm, output, *init_space)?;
         }
-        Commands::Compile { input, output } => {
-            let module: Module = parse_ts(input);
-            transpile(&module, output)?;
+        Commands::Layout { input, json } => {
+            print_layout(input, *json)?;
         }
         Commands::Init { name } => {
             init(name);
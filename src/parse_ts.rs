@@ -8,7 +8,9 @@ use swc_common::{
 use swc_ecma_ast::Module;
 use swc_ecma_parser::{lexer::Lexer, Capturing, Parser, StringInput, Syntax};
 
-pub fn parse_ts(input_file_name: &String) -> Module {
+/// Parses a TypeScript file. The source map is returned as well, so later passes can point
+/// their errors at the TypeScript source.
+pub fn parse_ts(input_file_name: &String) -> (Module, Lrc<SourceMap>) {
     let cm: Lrc<SourceMap> = Default::default();
     let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
 
@@ -36,5 +38,5 @@ pub fn parse_ts(input_file_name: &String) -> Module {
         .map_err(|e| e.into_diagnostic(&handler).emit())
         .expect("Failed to parse module.");
 
-    return module;
+    return (module, cm);
 }
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Result};
+use convert_case::{Case, Casing};
+use proc_macro2::{Ident, TokenStream};
+use quote::quote;
+use swc_ecma_ast::{ClassDecl, ClassMember};
+
+use crate::{
+    helpers::extract_type::extract_ts_type,
+    ts_types::{STANDARD_ARRAY_TYPES, STANDARD_TYPES},
+};
+
+#[derive(Debug, Clone)]
+pub struct ExternalMethod {
+    pub name: String,
+    pub accounts: Vec<String>,
+    pub args: Vec<String>,
+}
+
+/// An Anchor program declared in TypeScript with `declare class`. Its instructions are called
+/// through the `cpi` module that `declare_program!` generates from `idls/<program_name>.json`.
+/// Parameters typed as standard argument types are instruction arguments, the rest are accounts.
+#[derive(Debug, Clone)]
+pub struct ExternalProgram {
+    pub name: String,
+    pub methods: Vec<ExternalMethod>,
+}
+
+impl ExternalProgram {
+    pub fn from_class_decl(class_decl: ClassDecl) -> Result<Self> {
+        let name = class_decl.ident.sym.to_string();
+        if !class_decl.declare {
+            return Err(anyhow!(
+                "External program {} must be declared with `declare class`",
+                name
+            ));
+        }
+        let methods = class_decl
+            .class
+            .body
+            .iter()
+            .filter_map(|member| match member {
+                ClassMember::Method(method) => Some(Ok(method)),
+                // `PROGRAM_ID` is read from the IDL
+                ClassMember::ClassProp(_) => None,
+                _ => Some(Err(anyhow!("Invalid member of external program {}", name))),
+            })
+            .map(|method| {
+                let method = method?;
+                let method_name = method
+                    .key
+                    .as_ident()
+                    .ok_or(anyhow!(
+                        "the methods of {} must be named by identifiers",
+                        name
+                    ))?
+                    .sym
+                    .to_string();
+                let mut accounts = vec![];
+                let mut args = vec![];
+                for p in method.function.params.iter() {
+                    let ident = p.pat.as_ident().ok_or(anyhow!(
+                        "the parameters of {}.{} must be identifiers",
+                        name,
+                        method_name
+                    ))?;
+                    let type_ann = ident.type_ann.clone().ok_or(anyhow!(
+                        "{}.{} needs a type annotation for {}",
+                        name,
+                        method_name,
+                        ident.id.sym
+                    ))?;
+                    let (of_type, _, _) = extract_ts_type(type_ann).map_err(|_| {
+                        anyhow!(
+                            "unsupported type for {} in {}.{}",
+                            ident.id.sym,
+                            name,
+                            method_name
+                        )
+                    })?;
+                    if STANDARD_TYPES.contains(&of_type.as_str())
+                        || STANDARD_ARRAY_TYPES.contains(&of_type.as_str())
+                    {
+                        args.push(ident.id.sym.to_string());
+                    } else {
+                        accounts.push(ident.id.sym.to_string());
+                    }
+                }
+                Ok(ExternalMethod {
+                    name: method_name,
+                    accounts,
+                    args,
+                })
+            })
+            .collect::<Result<Vec<ExternalMethod>>>()?;
+        Ok(Self { name, methods })
+    }
+
+    pub fn get_method(&self, name: &str) -> Option<&ExternalMethod> {
+        self.methods.iter().find(|m| m.name == name)
+    }
+
+    pub fn to_tokens(&self) -> TokenStream {
+        let name = Ident::new(
+            &self.name.to_case(Case::Snake),
+            proc_macro2::Span::call_site(),
+        );
+        quote! {
+            declare_program!(#name);
+        }
+    }
+}
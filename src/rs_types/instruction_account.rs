@@ -17,13 +17,25 @@ pub struct InstructionAccount {
     pub is_close: bool,
     pub mint: Option<Mint>,
     pub ta: Option<Ta>,
-    pub has_one: Vec<String>,
+    pub has_one: Vec<(String, Option<TokenStream>)>,
     pub close: Option<String>,
+    pub close_error: Option<TokenStream>,
     pub seeds: Option<Vec<TokenStream>>,
     pub bump: Option<TokenStream>,
     pub payer: Option<String>,
     pub space: Option<u32>,
     pub is_custom: bool,
+    pub init_space: bool,
+    pub is_zero_copy: bool,
+    pub realloc: Option<TokenStream>,
+    pub realloc_payer: Option<String>,
+    pub is_signer: bool,
+    pub address: Option<TokenStream>,
+    pub address_error: Option<TokenStream>,
+    pub owner: Option<TokenStream>,
+    pub constraints: Vec<TokenStream>,
+    pub token_interface: bool,
+    pub mint_extensions: Vec<TokenStream>,
 }
 
 impl InstructionAccount {
@@ -41,17 +53,49 @@ impl InstructionAccount {
             ta: None,
             has_one: vec![],
             close: None,
+            close_error: None,
             seeds: None,
             bump: None,
             payer: None,
             space: None,
             is_custom: false,
+            init_space: false,
+            is_zero_copy: false,
+            realloc: None,
+            realloc_payer: None,
+            is_signer: false,
+            address: None,
+            address_error: None,
+            owner: None,
+            constraints: vec![],
+            token_interface: false,
+            mint_extensions: vec![],
+        }
+    }
+
+    /// Size of a custom account as used by `space` and by size-less `realloc`.
+    pub fn space_tokens(&self) -> Option<TokenStream> {
+        let ty = Ident::new(&self.type_str, proc_macro2::Span::call_site());
+        match self.space {
+            Some(_) if self.is_zero_copy => Some(quote! { 8 + std::mem::size_of::<#ty>() }),
+            Some(_) if self.init_space => Some(quote! { 8 + #ty::INIT_SPACE }),
+            Some(s) => {
+                let s_literal = Literal::u32_unsuffixed(s);
+                Some(quote! { #s_literal })
+            }
+            None => None,
         }
     }
 
     pub fn to_tokens(&self) -> TokenStream {
         let name = Ident::new(&self.name, proc_macro2::Span::call_site());
-        let of_type = &self.of_type;
+        let of_type = match self.optional {
+            true => {
+                let of_type = &self.of_type;
+                quote! { Option<#of_type> }
+            }
+            false => self.of_type.clone(),
+        };
         let constraints: TokenStream;
         // this is evaluated this way coz, ta might not have seeds
         if (self.mint.is_none() & self.seeds.is_none() & self.ta.is_none())
@@ -75,16 +119,25 @@ impl InstructionAccount {
             Some(a) => {
                 let mint = Ident::new(&a.mint, proc_macro2::Span::call_site());
                 let authority = Ident::new(&a.authority, proc_macro2::Span::call_site());
-                if a.is_ata {
-                    quote! {
+                match (a.is_ata, self.token_interface) {
+                    (true, false) => quote! {
                         associated_token::mint = #mint,
                         associated_token::authority = #authority,
-                    }
-                } else {
-                    quote! {
+                    },
+                    (true, true) => quote! {
+                        associated_token::mint = #mint,
+                        associated_token::authority = #authority,
+                        associated_token::token_program = token_program,
+                    },
+                    (false, false) => quote! {
                         token::mint = #mint,
                         token::authority = #authority,
-                    }
+                    },
+                    (false, true) => quote! {
+                        token::mint = #mint,
+                        token::authority = #authority,
+                        token::token_program = token_program,
+                    },
                 }
             }
             None => quote!(),
@@ -94,22 +147,30 @@ impl InstructionAccount {
             Some(m) => {
                 let decimal_token = &m.decimals_token;
                 let mint_auth_token = &m.mint_authority_token;
+                let mint_token_program = match self.token_interface {
+                    true => quote! { mint::token_program = token_program, },
+                    false => quote! {},
+                };
 
                 if let Some(freeze_auth) = &m.freeze_authority_token {
                     quote! {
                         mint::decimals = #decimal_token,
                         mint::authority = #mint_auth_token,
                         mint::freeze_authority = #freeze_auth,
+                        #mint_token_program
                     }
                 } else {
                     quote! {
                         mint::decimals = #decimal_token,
                         mint::authority = #mint_auth_token,
+                        #mint_token_program
                     }
                 }
             }
             None => quote! {},
         };
+        let mint_extensions = &self.mint_extensions;
+        let mint_extensions = quote! { #(#mint_extensions,)* };
         let close = match &self.close {
             Some(c) => {
                 let close_acc = Ident::new(c, proc_macro2::Span::call_site());
@@ -138,16 +199,54 @@ impl InstructionAccount {
             }
             None => quote! {},
         };
-        let space = match self.space {
-            Some(s) => {
-                let s_literal = Literal::u32_unsuffixed(s);
-                quote! {space = #s_literal,}
-            }
-            None => {
-                quote! {}
+        let space = match self.space_tokens() {
+            Some(s) => quote! {space = #s,},
+            None => quote! {},
+        };
+        let realloc = match (&self.realloc, &self.realloc_payer) {
+            (Some(size), Some(p)) => {
+                let realloc_payer =
+                    Ident::new(&p.to_case(Case::Snake), proc_macro2::Span::call_site());
+                quote! {
+                    realloc = #size,
+                    realloc::payer = #realloc_payer,
+                    realloc::zero = false,
+                }
             }
+            _ => quote! {},
         };
 
+        let signer = match self.is_signer {
+            true => quote! {signer,},
+            false => quote! {},
+        };
+        let address = match (&self.address, &self.address_error) {
+            (Some(a), Some(e)) => quote! {address = #a @ #e,},
+            (Some(a), None) => quote! {address = #a,},
+            _ => quote! {},
+        };
+        let owner = match &self.owner {
+            Some(o) => quote! {owner = #o,},
+            None => quote! {},
+        };
+        // anchor 0.30 has no `@ error` on `close` or `token::authority`, so the same
+        // checks are repeated as raw constraints, which anchor evaluates first
+        let mut custom_constraints = self.constraints.clone();
+        if let (Some(c), Some(e)) = (&self.close, &self.close_error) {
+            let close_acc = Ident::new(c, proc_macro2::Span::call_site());
+            custom_constraints.push(quote! { #name.key() != #close_acc.key() @ #e });
+        }
+        if let Some(Ta {
+            authority,
+            authority_error: Some(e),
+            ..
+        }) = &self.ta
+        {
+            let authority = Ident::new(authority, proc_macro2::Span::call_site());
+            custom_constraints.push(quote! { #name.owner == #authority.key() @ #e });
+        }
+        let custom_constraints = quote! { #(constraint = #custom_constraints,)* };
+
         let init = match self.is_init {
             true => quote! {init, #payer, #space},
             false => quote! {},
@@ -160,11 +259,16 @@ impl InstructionAccount {
         let mut has: TokenStream = quote! {};
         if !self.has_one.is_empty() {
             let mut has_vec: Vec<TokenStream> = vec![];
-            for h in &self.has_one {
+            for (h, error) in &self.has_one {
                 let h_ident = Ident::new(h, proc_macro2::Span::call_site());
-                has_vec.push(quote! {
-                    has_one = #h_ident
-                })
+                match error {
+                    Some(e) => has_vec.push(quote! {
+                        has_one = #h_ident @ #e
+                    }),
+                    None => has_vec.push(quote! {
+                        has_one = #h_ident
+                    }),
+                }
             }
             has = quote! { #(#has_vec),*,};
         }
@@ -184,12 +288,18 @@ impl InstructionAccount {
                 #init
                 #init_if_needed
                 #mutable
+                #signer
+                #realloc
                 #seeds
                 #ata
                 #mint
+                #mint_extensions
                 #has
                 #bump
                 #close
+                #address
+                #owner
+                #custom_constraints
 
             )]
         };
@@ -1,18 +1,26 @@
 use proc_macro2::TokenStream;
 
+pub mod external_program;
 pub mod instruction_account;
 pub mod program_account;
+pub mod program_constant;
+pub mod program_error;
 pub mod program_instruction;
 pub mod program_module;
+pub mod program_struct;
 
+pub use external_program::*;
 pub use program_account::*;
+pub use program_error::*;
 pub use program_module::*;
+pub use program_struct::*;
 
 #[derive(Debug, Clone)]
 pub struct Ta {
     mint: String,
     authority: String,
     is_ata: bool,
+    authority_error: Option<TokenStream>,
 }
 
 #[derive(Debug, Clone)]
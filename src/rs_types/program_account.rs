@@ -1,16 +1,38 @@
+use anyhow::{anyhow, Result};
 use convert_case::{Case, Casing};
 use core::panic;
-use proc_macro2::{Ident, TokenStream};
+use proc_macro2::{Ident, Literal, TokenStream};
 use quote::quote;
 use swc_ecma_ast::{TsExprWithTypeArgs, TsInterfaceDecl};
 
-use crate::helpers::extract_type::extract_ts_type;
-use crate::ts_types::rs_type_from_str;
+use crate::helpers::extract_type::{extract_max_len, extract_ts_type};
+use crate::ts_types::{rs_type_from_str, space_from_str};
 
 #[derive(Debug, Clone)]
 pub struct ProgramAccountField {
     pub name: String,
     pub of_type: String,
+    pub max_len: Vec<u32>,
+}
+
+impl ProgramAccountField {
+    pub fn is_dynamic(&self) -> bool {
+        self.of_type == "Str" || self.of_type == "Uint8Array" || self.of_type.starts_with("Vec<")
+    }
+
+    /// Serialized size with every `Str`/`Vec` at its declared max length.
+    pub fn max_space(&self) -> u32 {
+        space_from_str(&self.of_type, &self.max_len)
+            .unwrap_or_else(|_| panic!("Invalid type: {}", self.of_type))
+    }
+
+    /// Serialized size with every `Str`/`Vec` empty, i.e. just the length prefix.
+    pub fn min_space(&self) -> u32 {
+        match self.is_dynamic() {
+            true => 4,
+            false => self.max_space(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -18,16 +40,26 @@ pub struct ProgramAccount {
     pub name: String,
     pub fields: Vec<ProgramAccountField>,
     pub space: u32,
+    pub is_zero_copy: bool,
 }
 
 impl ProgramAccount {
     pub fn from_ts_expr(interface: TsInterfaceDecl) -> Self {
-        match interface.extends.first() {
+        let is_zero_copy = match interface.extends.first() {
             Some(TsExprWithTypeArgs { expr, .. })
                 if expr.clone().ident().is_some()
-                    && expr.clone().ident().unwrap().sym == "Account" => {}
-            _ => panic!("Custom accounts must extend Account type"),
-        }
+                    && expr.clone().ident().unwrap().sym == "Account" =>
+            {
+                false
+            }
+            Some(TsExprWithTypeArgs { expr, .. })
+                if expr.clone().ident().is_some()
+                    && expr.clone().ident().unwrap().sym == "ZeroCopyAccount" =>
+            {
+                true
+            }
+            _ => panic!("Custom accounts must extend Account or ZeroCopyAccount type"),
+        };
         let name: String = interface.id.sym.to_string();
         let mut space: u32 = 8; // anchor discriminator
         let fields: Vec<ProgramAccountField> = interface
@@ -38,43 +70,50 @@ impl ProgramAccount {
                 let field = f.clone().ts_property_signature().expect("Invalid property");
                 let field_name = field.key.ident().expect("Invalid property").sym.to_string();
                 let binding = field.type_ann.expect("Invalid type annotation");
-                let (field_type, len, _optional) = extract_ts_type(binding)
+                let max_len = extract_max_len(&binding)
+                    .unwrap_or_else(|_| panic!("Invalid length for {}", field_name));
+                let (field_type, _len, _optional) = extract_ts_type(binding)
                     .unwrap_or_else(|_| panic!("Keyword type is not supported"));
 
-                if field_type.contains("Vec") | field_type.contains("Str") {
-                    space += 4;
-                }
-
-                if field_type.contains("Pubkey") {
-                    space += 32 * len;
-                } else if field_type.contains("u64") | field_type.contains("i64") {
-                    space += 8 * len;
-                } else if field_type.contains("u32") | field_type.contains("i32") {
-                    space += 4 * len;
-                } else if field_type.contains("u16") | field_type.contains("i16") {
-                    space += 2 * len;
-                } else if field_type.contains("u8") | field_type.contains("i8") {
-                    space += 1 * len;
-                } else if field_type.contains("Str") {
-                    space += len;
-                } else if field_type.contains("Boolean") {
-                    space += len;
-                }
-
-                ProgramAccountField {
+                let field = ProgramAccountField {
                     name: field_name,
                     of_type: field_type.to_string(),
+                    max_len,
+                };
+                if is_zero_copy {
+                    // zero-copy accounts are cast in place, so every field must be fixed-size and Pod
+                    if field.is_dynamic() {
+                        panic!(
+                            "Zero-copy account {} cannot have dynamic field {}: {}, use Array<T, N> instead",
+                            name, field.name, field.of_type
+                        );
+                    }
+                    if field.of_type.contains("Boolean") {
+                        panic!(
+                            "Zero-copy account {} cannot have Boolean field {}, use u8 instead",
+                            name, field.name
+                        );
+                    }
                 }
+                space += field.max_space();
+                field
             })
             .collect();
+        if is_zero_copy {
+            check_zero_copy_layout(&name, &fields);
+        }
         Self {
             name,
             fields,
             space,
+            is_zero_copy,
         }
     }
 
-    pub fn to_tokens(&self) -> TokenStream {
+    /// With `init_space`, the struct derives `InitSpace` and dynamic fields carry `#[max_len(...)]`
+    /// so instructions can allocate `8 + T::INIT_SPACE` instead of the precomputed `space`.
+    /// Zero-copy accounts are always sized with `std::mem::size_of`.
+    pub fn to_tokens(&self, init_space: bool) -> Result<TokenStream> {
         let struct_name = Ident::new(&self.name, proc_macro2::Span::call_site());
 
         let fields: Vec<_> = self
@@ -86,18 +125,287 @@ impl ProgramAccount {
                     proc_macro2::Span::call_site(),
                 );
 
-                let field_type = rs_type_from_str(&field.of_type)
-                    .unwrap_or_else(|_| panic!("Invalid type: {}", field.of_type));
+                let field_type = match field.of_type.strip_prefix("Array<") {
+                    Some(elem_type) => {
+                        let elem_type = rs_type_from_str(elem_type.trim_end_matches('>'))
+                            .unwrap_or_else(|_| panic!("Invalid type: {}", field.of_type));
+                        let len = Literal::u32_unsuffixed(field.max_len[0]);
+                        quote! { [#elem_type; #len] }
+                    }
+                    None => rs_type_from_str(&field.of_type)
+                        .unwrap_or_else(|_| panic!("Invalid type: {}", field.of_type)),
+                };
 
-                quote! { pub #field_name: #field_type }
+                if !init_space || self.is_zero_copy {
+                    return Ok(quote! { pub #field_name: #field_type });
+                }
+                match field.of_type.as_str() {
+                    "usize" | "isize" => {
+                        return Err(anyhow!(
+                            "{} fields are not supported with InitSpace, use u64 or i64 instead",
+                            field.of_type
+                        ))
+                    }
+                    // a `Uint8Array` has no bound to give `#[max_len(...)]`
+                    "Uint8Array" => {
+                        return Err(anyhow!(
+                            "{}.{} has no max length for InitSpace, declare it as Vec<u8, N>",
+                            self.name,
+                            field.name
+                        ))
+                    }
+                    _ => {}
+                }
+                if field.is_dynamic() {
+                    let max_len = field.max_len.iter().map(|l| Literal::u32_unsuffixed(*l));
+                    Ok(quote! {
+                        #[max_len(#(#max_len),*)]
+                        pub #field_name: #field_type
+                    })
+                } else {
+                    Ok(quote! { pub #field_name: #field_type })
+                }
             })
-            .collect();
+            .collect::<Result<Vec<TokenStream>>>()?;
 
-        quote! {
+        if self.is_zero_copy {
+            return Ok(quote! {
+                #[account(zero_copy)]
+                #[repr(C)]
+                pub struct #struct_name {
+                    #(#fields),*
+                }
+            });
+        }
+
+        let derive_init_space = match init_space {
+            true => quote! { #[derive(InitSpace)] },
+            false => quote! {},
+        };
+
+        Ok(quote! {
             #[account]
+            #derive_init_space
             pub struct #struct_name {
                 #(#fields),*
             }
+        })
+    }
+}
+
+/// `#[account(zero_copy)]` derives `bytemuck::Pod`, which rejects `#[repr(C)]` structs with
+/// padding, so every field must start at a multiple of its alignment and the struct must end
+/// at a multiple of its largest alignment.
+fn check_zero_copy_layout(name: &str, fields: &[ProgramAccountField]) {
+    let mut offset = 0;
+    let mut struct_align = 1;
+    for field in fields {
+        let elem_type = field
+            .of_type
+            .strip_prefix("Array<")
+            .map_or(field.of_type.as_str(), |t| t.trim_end_matches('>'));
+        // `Pubkey` is a byte array, integers are aligned to their size
+        let align = match elem_type {
+            "Pubkey" => 1,
+            _ => space_from_str(elem_type, &[])
+                .unwrap_or_else(|_| panic!("Invalid type: {}", field.of_type)),
+        };
+        if offset % align != 0 {
+            panic!(
+                "Zero-copy account {} would have {} bytes of padding before field {}, order fields from the largest alignment to the smallest or add a padding: Array<u8, N> field",
+                name,
+                align - offset % align,
+                field.name
+            );
+        }
+        offset += field.max_space();
+        struct_align = struct_align.max(align);
+    }
+    if offset % struct_align != 0 {
+        panic!(
+            "Zero-copy account {} would have {} bytes of trailing padding, add a padding: Array<u8, {}> field",
+            name,
+            struct_align - offset % struct_align,
+            struct_align - offset % struct_align
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::*;
+    use swc_common::{BytePos, FileName, SourceMap};
+    use swc_ecma_ast::{Decl, ModuleDecl, ModuleItem};
+    use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax};
+
+    fn account_from_ts(src: &str) -> ProgramAccount {
+        let cm: SourceMap = Default::default();
+        let fm = cm.new_source_file(FileName::Anon, src.to_string());
+        let lexer = Lexer::new(
+            Syntax::Typescript(Default::default()),
+            Default::default(),
+            StringInput::new(&fm.src, BytePos(0), BytePos(fm.src.len() as u32)),
+            None,
+        );
+        let module = Parser::new_from(lexer)
+            .parse_typescript_module()
+            .expect("Failed to parse module.");
+        match &module.body[0] {
+            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(export)) => match &export.decl {
+                Decl::TsInterface(interface) => ProgramAccount::from_ts_expr(*interface.clone()),
+                _ => unreachable!("expected an interface"),
+            },
+            _ => unreachable!("expected an export"),
         }
     }
+
+    // Types and lengths are matched as raw tokens: `$ty` fragments reach the derive as
+    // invisible groups, which `InitSpace` cannot size.
+    macro_rules! assert_space_matches_init_space {
+        ($ts_type:literal, max_len($($len:tt),+), $($rs_type:tt)+) => {{
+            #[derive(InitSpace)]
+            #[allow(dead_code)]
+            struct Expected {
+                #[max_len($($len),+)]
+                field: $($rs_type)+,
+            }
+            assert_space_matches_init_space!(@check $ts_type, Expected)
+        }};
+        ($ts_type:literal, $($rs_type:tt)+) => {{
+            #[derive(InitSpace)]
+            #[allow(dead_code)]
+            struct Expected {
+                field: $($rs_type)+,
+            }
+            assert_space_matches_init_space!(@check $ts_type, Expected)
+        }};
+        (@check $ts_type:literal, $expected:ident) => {{
+            let account = account_from_ts(concat!(
+                "export interface Test extends Account { field: ",
+                $ts_type,
+                " }"
+            ));
+            assert_eq!(
+                account.space as usize,
+                8 + $expected::INIT_SPACE,
+                "space mismatch for {}",
+                $ts_type
+            );
+        }};
+    }
+
+    #[test]
+    fn space_matches_init_space_for_standard_types() {
+        assert_space_matches_init_space!("u8", u8);
+        assert_space_matches_init_space!("i8", i8);
+        assert_space_matches_init_space!("u16", u16);
+        assert_space_matches_init_space!("i16", i16);
+        assert_space_matches_init_space!("u32", u32);
+        assert_space_matches_init_space!("i32", i32);
+        assert_space_matches_init_space!("u64", u64);
+        assert_space_matches_init_space!("i64", i64);
+        assert_space_matches_init_space!("u128", u128);
+        assert_space_matches_init_space!("i128", i128);
+        assert_space_matches_init_space!("Boolean", bool);
+        assert_space_matches_init_space!("Pubkey", Pubkey);
+        assert_space_matches_init_space!("Str<32>", max_len(32), String);
+        assert_space_matches_init_space!("Uint8Array", max_len(0), Vec<u8>);
+    }
+
+    #[test]
+    fn space_matches_init_space_for_array_types() {
+        assert_space_matches_init_space!("Vec<u8, 10>", max_len(10), Vec<u8>);
+        assert_space_matches_init_space!("Vec<i8, 10>", max_len(10), Vec<i8>);
+        assert_space_matches_init_space!("Vec<u16, 10>", max_len(10), Vec<u16>);
+        assert_space_matches_init_space!("Vec<i16, 10>", max_len(10), Vec<i16>);
+        assert_space_matches_init_space!("Vec<u32, 10>", max_len(10), Vec<u32>);
+        assert_space_matches_init_space!("Vec<i32, 10>", max_len(10), Vec<i32>);
+        assert_space_matches_init_space!("Vec<u64, 10>", max_len(10), Vec<u64>);
+        assert_space_matches_init_space!("Vec<i64, 10>", max_len(10), Vec<i64>);
+        assert_space_matches_init_space!("Vec<u128, 10>", max_len(10), Vec<u128>);
+        assert_space_matches_init_space!("Vec<i128, 10>", max_len(10), Vec<i128>);
+        assert_space_matches_init_space!("Vec<Pubkey, 10>", max_len(10), Vec<Pubkey>);
+        assert_space_matches_init_space!("Vec<Boolean, 10>", max_len(10), Vec<bool>);
+        assert_space_matches_init_space!("Vec<Str<20>, 5>", max_len(5, 20), Vec<String>);
+    }
+
+    #[test]
+    fn pointer_sized_fields_use_eight_bytes() {
+        assert_eq!(
+            account_from_ts("export interface Test extends Account { a: usize, b: isize }").space,
+            8 + 8 + 8
+        );
+    }
+
+    #[test]
+    fn init_space_tokens_carry_max_len() {
+        let account = account_from_ts(
+            "export interface Test extends Account { name: Str<32>, hobbies: Vec<Str<20>, 5>, count: u64 }",
+        );
+        let tokens = account.to_tokens(true).unwrap().to_string();
+        assert!(tokens.contains("# [derive (InitSpace)]"));
+        assert!(tokens.contains("# [max_len (32)] pub name : String"));
+        assert!(tokens.contains("# [max_len (5 , 20)] pub hobbies : Vec < String >"));
+        assert!(tokens.contains("pub count : u64"));
+        assert!(!account
+            .to_tokens(false)
+            .unwrap()
+            .to_string()
+            .contains("InitSpace"));
+    }
+
+    #[test]
+    fn init_space_rejects_unbounded_byte_arrays() {
+        let account = account_from_ts("export interface Test extends Account { data: Uint8Array }");
+        assert!(account.to_tokens(false).is_ok());
+        assert_eq!(
+            account.to_tokens(true).unwrap_err().to_string(),
+            "Test.data has no max length for InitSpace, declare it as Vec<u8, N>"
+        );
+    }
+
+    #[test]
+    fn space_matches_init_space_for_fixed_arrays() {
+        assert_space_matches_init_space!("Array<u64, 4>", [u64; 4]);
+        assert_space_matches_init_space!("Array<Pubkey, 3>", [Pubkey; 3]);
+    }
+
+    #[test]
+    fn zero_copy_tokens_use_fixed_arrays() {
+        let account = account_from_ts(
+            "export interface Book extends ZeroCopyAccount { bids: Array<u64, 32>, count: u64 }",
+        );
+        assert!(account.is_zero_copy);
+        let tokens = account.to_tokens(true).unwrap().to_string();
+        assert!(tokens.contains("# [account (zero_copy)] # [repr (C)]"));
+        assert!(tokens.contains("pub bids : [u64 ; 32]"));
+        assert!(!tokens.contains("InitSpace"));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot have dynamic field")]
+    fn zero_copy_rejects_dynamic_fields() {
+        account_from_ts("export interface Book extends ZeroCopyAccount { name: Str<10> }");
+    }
+
+    #[test]
+    #[should_panic(expected = "bytes of padding before field count")]
+    fn zero_copy_rejects_padding_between_fields() {
+        account_from_ts("export interface Book extends ZeroCopyAccount { bump: u8, count: u64 }");
+    }
+
+    #[test]
+    #[should_panic(expected = "4 bytes of trailing padding")]
+    fn zero_copy_rejects_trailing_padding() {
+        account_from_ts("export interface Book extends ZeroCopyAccount { bids: Array<u64, 2>, count: u32 }");
+    }
+
+    #[test]
+    fn zero_copy_accepts_explicit_padding() {
+        let account = account_from_ts(
+            "export interface Book extends ZeroCopyAccount { authority: Pubkey, bids: Array<u64, 32>, count: u32, bump: u8, padding: Array<u8, 3> }",
+        );
+        assert!(account.is_zero_copy);
+    }
 }
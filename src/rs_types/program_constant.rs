@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result};
+use convert_case::{Case, Casing};
+use core::panic;
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::quote;
+use swc_ecma_ast::{ClassProp, Expr, Lit, UnaryOp};
+
+use crate::helpers::extract_type::extract_ts_type;
+use crate::ts_types::rs_type_from_str;
+use crate::type_checker::{int_literal_value, literal_fits};
+
+/// A static property of the program class, e.g. `static readonly MAX_ITEMS = 10` or
+/// `static FEE_BPS: u16 = 30`. Without a type annotation, integers are `u64` (`i64` when negative,
+/// `u128`/`i128` when they don't fit), strings are `&str`, booleans are `bool` and
+/// `new Pubkey("...")` is a `Pubkey`.
+#[derive(Debug, Clone)]
+pub struct ProgramConstant {
+    pub name: String,
+    pub of_type: String,
+    pub value: TokenStream,
+}
+
+impl ProgramConstant {
+    pub fn from_class_prop(prop: &ClassProp) -> Result<Self> {
+        let name = prop
+            .key
+            .as_ident()
+            .ok_or(anyhow!("constants must be named by identifiers"))?
+            .sym
+            .to_string();
+        if !prop.is_static {
+            return Err(anyhow!("Constant {} must be static", name));
+        }
+        // `declare_id!` already defines `ID`
+        if name.to_case(Case::UpperSnake) == "ID" {
+            return Err(anyhow!(
+                "Constant {} clashes with the program ID, rename it",
+                name
+            ));
+        }
+        let value = prop
+            .value
+            .as_ref()
+            .ok_or(anyhow!("Constant {} must have a value", name))?;
+        let integer =
+            |lit: &Lit| int_literal_value(lit).map_err(|e| anyhow!("Constant {}: {}", name, e));
+        let (inferred_type, value) = match value.as_ref() {
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => {
+                let value = integer(lit)?;
+                let of_type = match literal_fits(false, value, "u64") {
+                    true => "u64",
+                    false => "u128",
+                };
+                let value = Literal::u128_unsuffixed(value);
+                (of_type, quote! { #value })
+            }
+            Expr::Unary(unary) if unary.op == UnaryOp::Minus => match unary.arg.as_ref() {
+                Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => {
+                    let value = integer(lit)?;
+                    let of_type = match literal_fits(true, value, "i64") {
+                        true => "i64",
+                        false => "i128",
+                    };
+                    let value = Literal::u128_unsuffixed(value);
+                    (of_type, quote! { -#value })
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Constant {} must be an integer, string or boolean",
+                        name
+                    ))
+                }
+            },
+            Expr::Lit(Lit::Str(s)) => {
+                let value = Literal::string(&s.value);
+                ("Str", quote! { #value })
+            }
+            Expr::Lit(Lit::Bool(b)) => {
+                let value = b.value;
+                ("Boolean", quote! { #value })
+            }
+            Expr::New(new_expr)
+                if new_expr
+                    .callee
+                    .as_ident()
+                    .is_some_and(|c| c.sym == "Pubkey") =>
+            {
+                let key = match new_expr
+                    .args
+                    .as_ref()
+                    .and_then(|args| args.first())
+                    .map(|arg| arg.expr.as_ref())
+                {
+                    Some(Expr::Lit(Lit::Str(s))) => Literal::string(&s.value),
+                    _ => return Err(anyhow!("Constant {} must be new Pubkey(\"...\")", name)),
+                };
+                ("Pubkey", quote! { pubkey!(#key) })
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Constant {} must be an integer, string, boolean or pubkey",
+                    name
+                ))
+            }
+        };
+        let of_type = match prop.type_ann.clone() {
+            Some(type_ann) => {
+                let of_type = extract_ts_type(type_ann)
+                    .map_err(|_| anyhow!("unsupported type for constant {}", name))?
+                    .0;
+                if of_type != "Str" {
+                    rs_type_from_str(&of_type).map_err(|_| {
+                        anyhow!("unsupported type {} for constant {}", of_type, name)
+                    })?;
+                }
+                of_type
+            }
+            None => inferred_type.to_string(),
+        };
+        Ok(Self {
+            name,
+            of_type,
+            value,
+        })
+    }
+
+    pub fn ident(&self) -> Ident {
+        Ident::new(
+            &self.name.to_case(Case::UpperSnake),
+            proc_macro2::Span::call_site(),
+        )
+    }
+
+    pub fn to_tokens(&self) -> TokenStream {
+        let name = self.ident();
+        let of_type = match self.of_type.as_str() {
+            "Str" => quote! { &str },
+            _ => rs_type_from_str(&self.of_type)
+                .unwrap_or_else(|_| panic!("Invalid type: {}", self.of_type)),
+        };
+        let value = &self.value;
+        quote! {
+            #[constant]
+            pub const #name: #of_type = #value;
+        }
+    }
+}
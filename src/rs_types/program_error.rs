@@ -0,0 +1,70 @@
+use core::panic;
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::quote;
+use swc_ecma_ast::{Expr, Lit, TsEnumDecl};
+
+#[derive(Debug, Clone)]
+pub struct ProgramErrorVariant {
+    pub name: String,
+    pub msg: Option<String>,
+}
+
+/// A TypeScript `enum` transpiled into an Anchor `#[error_code]` enum. String initializers
+/// become the `#[msg(...)]` of the variant.
+#[derive(Debug, Clone)]
+pub struct ProgramError {
+    pub name: String,
+    pub variants: Vec<ProgramErrorVariant>,
+}
+
+impl ProgramError {
+    pub fn from_ts_enum(ts_enum: TsEnumDecl) -> Self {
+        let name = ts_enum.id.sym.to_string();
+        let variants = ts_enum
+            .members
+            .iter()
+            .map(|member| {
+                let msg = match member.init.as_deref() {
+                    Some(Expr::Lit(Lit::Str(s))) => Some(s.value.to_string()),
+                    Some(_) => panic!(
+                        "Error variants of {} can only be initialized with a message string",
+                        name
+                    ),
+                    None => None,
+                };
+                ProgramErrorVariant {
+                    name: member.id.as_ref().to_string(),
+                    msg,
+                }
+            })
+            .collect();
+        Self { name, variants }
+    }
+
+    pub fn to_tokens(&self) -> TokenStream {
+        let name = Ident::new(&self.name, proc_macro2::Span::call_site());
+        let variants: Vec<TokenStream> = self
+            .variants
+            .iter()
+            .map(|v| {
+                let variant = Ident::new(&v.name, proc_macro2::Span::call_site());
+                match &v.msg {
+                    Some(msg) => {
+                        let msg_literal = Literal::string(msg);
+                        quote! {
+                            #[msg(#msg_literal)]
+                            #variant
+                        }
+                    }
+                    None => quote! { #variant },
+                }
+            })
+            .collect();
+        quote! {
+            #[error_code]
+            pub enum #name {
+                #(#variants),*
+            }
+        }
+    }
+}
@@ -1,20 +1,24 @@
 use convert_case::{Case, Casing};
 use core::panic;
-use proc_macro2::{Ident, Literal, Span, TokenStream};
+use proc_macro2::{Ident, Literal, Span, TokenStream, TokenTree};
 use quote::quote;
 use std::collections::HashMap;
 use swc_ecma_ast::{
-    BindingIdent, CallExpr, ClassMethod, Expr, ExprOrSpread, Lit, MemberExpr, Stmt,
+    AssignOp, BinaryOp, BindingIdent, BreakStmt, CallExpr, ClassMethod, ContinueStmt, Expr,
+    ExprOrSpread, ForHead, ForOfStmt, ForStmt, Lit, MemberExpr, Pat, Stmt, UnaryOp, UpdateOp, VarDeclOrExpr,
 };
 
 use crate::{
     errors::PoseidonError,
     helpers::extract_type::extract_ts_type,
     rs_types::{
-        instruction_account::InstructionAccount, program_account::ProgramAccount,
-        program_module::ProgramModule, Mint, Ta,
+        instruction_account::InstructionAccount,
+        program_account::{ProgramAccount, ProgramAccountField},
+        program_constant::ProgramConstant, program_module::ProgramModule,
+        program_struct::ProgramStruct, Mint, Ta,
     },
     ts_types::{rs_type_from_str, STANDARD_ACCOUNT_TYPES, STANDARD_ARRAY_TYPES, STANDARD_TYPES},
+    type_checker::{cast_call, find_program_address_call, int_literal_value, is_lossless_cast, literal_fits},
 };
 use anyhow::{anyhow, Ok, Result};
 
@@ -35,7 +39,321 @@ pub struct ProgramInstruction {
     pub uses_system_program: bool,
     pub uses_token_program: bool,
     pub uses_associated_token_program: bool,
+    pub uses_token_interface: bool,
+    pub uses_metadata_program: bool,
+    pub uses_rent_sysvar: bool,
+    pub external_programs: Vec<String>,
+    pub uses_remaining_accounts: bool,
+    pub loop_accounts: Vec<String>, // loop variables bound to remaining accounts
+    pub bound_accounts: Vec<String>, // optional accounts bound by the enclosing `if let Some(...)`
+    pub struct_args: HashMap<String, ProgramStruct>, // arg name : struct type
+    pub program_name: String,
+    pub constants: Vec<ProgramConstant>,
+    pub casts: HashMap<swc_common::Span, String>, // span of a cast call : type of its value
     pub instruction_attributes: Option<Vec<TokenStream>>,
+    pub zero_copy_accounts: Vec<String>,
+    // zero-copy accounts whose fields the statement being lowered reads or writes
+    pub zero_copy_uses: Vec<String>,
+}
+
+/// An instruction body statement. Loops and `if` blocks are flattened into markers around the
+/// statements of their body, so those are lowered like top level statements.
+enum BodyItem {
+    Stmt(Stmt),
+    BlockStart(Stmt),
+    Else,
+    BlockEnd,
+}
+
+/// A loop or `if` block whose body is being lowered.
+struct OpenBlock {
+    outer_body: Vec<TokenStream>,
+    outer_guards: Vec<Vec<String>>,
+    header: TokenStream,
+    // zero-copy accounts used by the header, whose guard is held for the whole block
+    guards: Vec<String>,
+    // the optional account bound by `if let Some(...)`
+    bound_account: Option<String>,
+    // the body before `else`
+    then_body: Option<(Vec<TokenStream>, Vec<Vec<String>>)>,
+}
+
+/// Flattens `stmts` into body items. `in_loop` is whether they are inside a loop, where
+/// `break` and `continue` are allowed.
+fn flatten_blocks(stmts: &[Stmt], items: &mut Vec<BodyItem>, in_loop: bool) -> Result<()> {
+    let flatten_body = |body: &Stmt, items: &mut Vec<BodyItem>, in_loop: bool| match body {
+        Stmt::Block(block) => flatten_blocks(&block.stmts, items, in_loop),
+        body => flatten_blocks(std::slice::from_ref(body), items, in_loop),
+    };
+    for stmt in stmts {
+        match stmt {
+            Stmt::For(ForStmt { body, .. }) | Stmt::ForOf(ForOfStmt { body, .. }) => {
+                items.push(BodyItem::BlockStart(stmt.clone()));
+                flatten_body(body, items, true)?;
+            }
+            Stmt::If(if_stmt) => {
+                items.push(BodyItem::BlockStart(stmt.clone()));
+                flatten_body(&if_stmt.cons, items, in_loop)?;
+                if let Some(alt) = &if_stmt.alt {
+                    items.push(BodyItem::Else);
+                    flatten_body(alt, items, in_loop)?;
+                }
+            }
+            Stmt::Break(BreakStmt { label: Some(_), .. }) | Stmt::Continue(ContinueStmt { label: Some(_), .. }) => {
+                return Err(anyhow!("labeled break and continue statements are not supported"))
+            }
+            Stmt::Break(_) | Stmt::Continue(_) if !in_loop => {
+                return Err(anyhow!("break and continue can only be used inside a for loop"))
+            }
+            Stmt::While(_) | Stmt::DoWhile(_) => {
+                return Err(anyhow!(
+                    "while loops are not supported, since they can exhaust the compute budget. Use a bounded for loop instead"
+                ))
+            }
+            Stmt::ForIn(_) => return Err(anyhow!("for...in loops are not supported, use for...of")),
+            _ => {
+                items.push(BodyItem::Stmt(stmt.clone()));
+                continue;
+            }
+        }
+        items.push(BodyItem::BlockEnd);
+    }
+    Ok(())
+}
+
+/// Whether `ctx.accounts.<name>` is used anywhere in `tokens`, apart from the
+/// `ctx.accounts.<name>.as_mut()` that binds it in `if let Some(<name>) = ...`.
+fn references_account(tokens: &TokenStream, name: &str) -> bool {
+    let tokens: Vec<TokenTree> = tokens.clone().into_iter().collect();
+    let is_binding = |rest: &[TokenTree]| {
+        matches!(rest, [TokenTree::Punct(p), TokenTree::Ident(m), ..] if p.as_char() == '.' && m == "as_mut")
+    };
+    (0..tokens.len()).any(|i| {
+        tokens.len() >= i + 5
+            && is_account_path(&tokens[i..i + 5], name)
+            && !is_binding(&tokens[i + 5..])
+    }) || tokens.iter().any(|t| match t {
+        TokenTree::Group(g) => references_account(&g.stream(), name),
+        _ => false,
+    })
+}
+
+fn is_account_path(tokens: &[TokenTree], name: &str) -> bool {
+    matches!(tokens, [TokenTree::Ident(ctx), TokenTree::Punct(p1), TokenTree::Ident(accounts), TokenTree::Punct(p2), TokenTree::Ident(acc)]
+        if ctx == "ctx" && p1.as_char() == '.' && accounts == "accounts" && p2.as_char() == '.' && acc == name)
+}
+
+/// Whether `tokens` use `ctx.accounts.<name>` for anything but its key, e.g. to pass its
+/// `AccountInfo` to a CPI, which borrows the account data a zero-copy guard holds.
+fn borrows_account(tokens: &TokenStream, name: &str) -> bool {
+    let tokens: Vec<TokenTree> = tokens.clone().into_iter().collect();
+    let is_key = |rest: &[TokenTree]| {
+        matches!(rest, [TokenTree::Punct(p), TokenTree::Ident(m), ..] if p.as_char() == '.' && m == "key")
+    };
+    (0..tokens.len()).any(|i| {
+        tokens.len() >= i + 5 && is_account_path(&tokens[i..i + 5], name) && !is_key(&tokens[i + 5..])
+    }) || tokens.iter().any(|t| match t {
+        TokenTree::Group(g) => borrows_account(&g.stream(), name),
+        _ => false,
+    })
+}
+
+/// Binds the guard of each zero-copy account right before the statements of `body` that use
+/// its fields (`guards[i]` lists the accounts statement `i` uses) and drops it after them, so
+/// CPIs in between can borrow the account.
+fn scope_zero_copy_guards(
+    ix_accounts: &HashMap<String, InstructionAccount>,
+    body: Vec<TokenStream>,
+    guards: Vec<Vec<String>>,
+) -> Result<Vec<TokenStream>> {
+    let mut scoped: Vec<TokenStream> = vec![];
+    let mut bound: Vec<String> = vec![];
+    let len = body.len();
+    for (i, stmt) in body.into_iter().enumerate() {
+        for acc_name in guards[i].iter() {
+            if borrows_account(&stmt, acc_name) {
+                return Err(anyhow!(
+                    "the fields of zero-copy account {} can't be used in the statement or block that passes {} to a CPI",
+                    acc_name, acc_name
+                ));
+            }
+            if bound.contains(acc_name) {
+                continue;
+            }
+            let acc = ix_accounts
+                .values()
+                .find(|acc| &acc.name == acc_name)
+                .ok_or(anyhow!("unknown zero-copy account {}", acc_name))?;
+            let acc_ident = Ident::new(acc_name, Span::call_site());
+            scoped.push(if acc.is_init {
+                quote! { let mut #acc_ident = ctx.accounts.#acc_ident.load_init()?; }
+            } else if acc.is_mut {
+                quote! { let mut #acc_ident = ctx.accounts.#acc_ident.load_mut()?; }
+            } else {
+                quote! { let #acc_ident = ctx.accounts.#acc_ident.load()?; }
+            });
+            bound.push(acc_name.clone());
+        }
+        scoped.push(stmt);
+        // guards go out of scope at the end of the body anyway
+        if i + 1 < len {
+            let (kept, dropped): (Vec<String>, Vec<String>) =
+                bound.into_iter().partition(|acc| guards[i + 1].contains(acc));
+            for acc in dropped {
+                let acc_ident = Ident::new(&acc, Span::call_site());
+                scoped.push(quote! { drop(#acc_ident); });
+            }
+            bound = kept;
+        }
+    }
+    Ok(scoped)
+}
+
+/// The `Vec` or `Str` field of a custom account that `expr` (`acc.field`) refers to, along with
+/// the name of the account.
+fn collection_field<'a>(
+    ix_accounts: &HashMap<String, InstructionAccount>,
+    custom_accounts: &'a HashMap<String, ProgramAccount>,
+    expr: &Expr,
+) -> Option<(String, &'a ProgramAccountField)> {
+    let member = expr.as_member()?;
+    let acc = member.obj.as_ident()?.sym.to_string();
+    let prop = member.prop.as_ident()?.sym.as_ref();
+    let field = custom_accounts
+        .get(&ix_accounts.get(&acc)?.type_str)?
+        .fields
+        .iter()
+        .find(|f| f.name == prop)?;
+    match field.of_type == "Str" || field.of_type.starts_with("Vec<") {
+        true => Some((acc, field)),
+        false => None,
+    }
+}
+
+/// The instruction account whose balance `expr` (`acc.lamports`) reads, unless the account has a
+/// `lamports` field of its own.
+fn lamports_account(
+    ix_accounts: &HashMap<String, InstructionAccount>,
+    custom_accounts: &HashMap<String, ProgramAccount>,
+    expr: &Expr,
+) -> Option<String> {
+    let member = expr.as_member()?;
+    let acc = member.obj.as_ident()?.sym.to_string();
+    if member.prop.as_ident()?.sym != "lamports" {
+        return None;
+    }
+    let has_field = custom_accounts
+        .get(&ix_accounts.get(&acc)?.type_str)
+        .is_some_and(|a| a.fields.iter().any(|f| f.name == "lamports"));
+    match has_field {
+        true => None,
+        false => Some(acc),
+    }
+}
+
+/// The Rust operator for a TypeScript binary operator.
+fn rs_binary_op(op: BinaryOp) -> Result<TokenStream> {
+    Ok(match op {
+        BinaryOp::EqEq | BinaryOp::EqEqEq => quote! { == },
+        BinaryOp::NotEq | BinaryOp::NotEqEq => quote! { != },
+        BinaryOp::Lt => quote! { < },
+        BinaryOp::LtEq => quote! { <= },
+        BinaryOp::Gt => quote! { > },
+        BinaryOp::GtEq => quote! { >= },
+        BinaryOp::LogicalAnd => quote! { && },
+        BinaryOp::LogicalOr => quote! { || },
+        BinaryOp::Add => quote! { + },
+        BinaryOp::Sub => quote! { - },
+        BinaryOp::Mul => quote! { * },
+        BinaryOp::Div => quote! { / },
+        BinaryOp::Mod => quote! { % },
+        BinaryOp::BitAnd => quote! { & },
+        BinaryOp::BitOr => quote! { | },
+        BinaryOp::BitXor => quote! { ^ },
+        BinaryOp::LShift => quote! { << },
+        BinaryOp::RShift => quote! { >> },
+        _ => return Err(anyhow!("unsupported binary operator {}", op)),
+    })
+}
+
+/// Bitwise operators bind tighter than comparisons in Rust but looser in TypeScript, and Rust
+/// comparisons can't be chained, so such an operand of `op` keeps its grouping in parentheses.
+fn needs_parens(op: BinaryOp, operand: &Expr) -> bool {
+    let Expr::Bin(inner) = operand else {
+        return false;
+    };
+    let is_bitwise = |op: BinaryOp| matches!(op, BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor);
+    let is_comparison = |op: BinaryOp| {
+        matches!(
+            op,
+            BinaryOp::EqEq
+                | BinaryOp::EqEqEq
+                | BinaryOp::NotEq
+                | BinaryOp::NotEqEq
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq
+        )
+    };
+    is_bitwise(op) || is_bitwise(inner.op) || (is_comparison(op) && is_comparison(inner.op))
+}
+
+/// The operator of a method helper such as `a.add(b)`, `a.lt(b)` or `a.key.equals(b.key)`.
+fn helper_op(method: &str) -> Option<TokenStream> {
+    Some(match method {
+        "add" => quote! { + },
+        "sub" => quote! { - },
+        "mul" => quote! { * },
+        "div" => quote! { / },
+        "mod" => quote! { % },
+        "eq" => quote! { == },
+        "neq" => quote! { != },
+        "lt" => quote! { < },
+        "lte" => quote! { <= },
+        "gt" => quote! { > },
+        "gte" => quote! { >= },
+        "equals" => quote! { == },
+        _ => return None,
+    })
+}
+
+/// Lowers `value.toU64()` and the other integer casts. A conversion that can't lose information
+/// is `u64::from(value)`, any other one fails the instruction if the value doesn't fit.
+fn rs_cast(value: TokenStream, from: Option<&String>, to: &str) -> TokenStream {
+    let to = Ident::new(to, Span::call_site());
+    match from {
+        Some(from) if is_lossless_cast(from, &to.to_string()) => quote! { #to::from(#value) },
+        _ => quote! { #to::try_from(#value).map_err(|_| ProgramError::ArithmeticOverflow)? },
+    }
+}
+
+/// Lowers an integer literal. Where the Rust type is fixed, such as the `u8` bytes of
+/// instruction data, the value must fit in it.
+fn rs_int_literal(lit: &Lit, of_type: Option<&str>) -> Result<TokenStream> {
+    let value = int_literal_value(lit).map_err(|e| anyhow!(e))?;
+    if let Some(of_type) = of_type.filter(|t| !literal_fits(false, value, t)) {
+        return Err(anyhow!("literal `{}` is out of range for `{}`", value, of_type));
+    }
+    let value = Literal::u128_unsuffixed(value);
+    Ok(quote! { #value })
+}
+
+/// Whether `expr` is a call of a method helper, e.g. `a.add(b)`.
+fn is_helper_call(expr: &Expr) -> bool {
+    expr.as_call()
+        .and_then(|c| c.callee.as_expr())
+        .and_then(|callee| callee.as_member())
+        .and_then(|m| m.prop.as_ident())
+        .is_some_and(|p| helper_op(p.sym.as_ref()).is_some())
+}
+
+/// Whether `expr` is `Context.remainingAccounts`.
+fn is_remaining_accounts(expr: &Expr) -> bool {
+    expr.as_member().is_some_and(|m| {
+        m.obj.as_ident().is_some_and(|o| o.sym == "Context")
+            && m.prop.as_ident().is_some_and(|p| p.sym == "remainingAccounts")
+    })
 }
 
 impl ProgramInstruction {
@@ -49,8 +367,1151 @@ impl ProgramInstruction {
             uses_system_program: false,
             uses_token_program: false,
             uses_associated_token_program: false,
+            uses_token_interface: false,
+            uses_metadata_program: false,
+            uses_rent_sysvar: false,
+            external_programs: vec![],
+            uses_remaining_accounts: false,
+            loop_accounts: vec![],
+            bound_accounts: vec![],
+            struct_args: HashMap::new(),
+            program_name: String::new(),
+            constants: vec![],
+            casts: HashMap::new(),
             instruction_attributes: None,
+            zero_copy_accounts: vec![],
+            zero_copy_uses: vec![],
+        }
+    }
+
+    /// Adds args used by account constraints to `#[instruction(...)]`, skipping ones already listed.
+    pub fn add_instruction_attributes(&mut self, attributes: Vec<TokenStream>) {
+        let existing = self.instruction_attributes.get_or_insert_with(Vec::new);
+        for attribute in attributes {
+            if !existing.iter().any(|a| a.to_string() == attribute.to_string()) {
+                existing.push(attribute);
+            }
+        }
+        if existing.is_empty() {
+            self.instruction_attributes = None;
+        }
+    }
+
+    /// Handles `.realloc(newSize, payer)`.
+    pub fn set_realloc(
+        &mut self,
+        acc: &mut InstructionAccount,
+        args: &[ExprOrSpread],
+    ) -> Result<()> {
+        // accounts are created at the full size of their type, so realloc needs an explicit size
+        let (size_expr, payer_expr) = match args {
+            [size, payer] => (size, payer),
+            _ => {
+                return Err(anyhow!(
+                    "Pass the new size and payer account arguments for realloc of {}",
+                    acc.name
+                ))
+            }
+        };
+        let size = match size_expr.expr.as_ref() {
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, Some("usize"))?,
+            size_expr => {
+                let size = self.get_rs_arg_from_ts_arg(size_expr, true)?;
+                quote! { #size as usize }
+            }
+        };
+        acc.realloc = Some(size);
+        acc.realloc_payer = Some(
+            payer_expr
+                .expr
+                .as_ident()
+                .ok_or(PoseidonError::IdentNotFound)?
+                .sym
+                .as_ref()
+                .to_case(Case::Snake),
+        );
+        acc.is_mut = true;
+        self.uses_system_program = true;
+        Ok(())
+    }
+
+    /// An instruction account, `ctx.accounts.<name>`, or the variable binding it inside
+    /// `if (<name>) { ... }` when it is optional.
+    pub fn get_rs_account(&self, name: &str) -> TokenStream {
+        let name = name.to_case(Case::Snake);
+        let ident = Ident::new(&name, Span::call_site());
+        match self.bound_accounts.contains(&name) {
+            true => quote! { #ident },
+            false => quote! { ctx.accounts.#ident },
+        }
+    }
+
+    /// Field access on an instruction account. Zero-copy accounts are read through their
+    /// `load`/`load_mut`/`load_init` guard, which is bound around the statements using it.
+    pub fn account_field(&mut self, obj: &Ident, prop: &Ident) -> Result<TokenStream> {
+        if let Some(arg_struct) = self.struct_args.get(&obj.to_string()) {
+            // struct arguments are owned, so dynamic fields are cloned to keep them usable
+            return match arg_struct.get_field(&prop.to_string()) {
+                Some(field) if field.is_dynamic() => Ok(quote! { #obj.#prop.clone() }),
+                Some(_) => Ok(quote! { #obj.#prop }),
+                None => Err(anyhow!("{} has no field {}", arg_struct.name, prop)),
+            };
+        }
+        if self.zero_copy_accounts.contains(&obj.to_string()) {
+            if !self.zero_copy_uses.contains(&obj.to_string()) {
+                self.zero_copy_uses.push(obj.to_string());
+            }
+            Ok(quote! { #obj.#prop })
+        } else {
+            let acc = self.get_rs_account(&obj.to_string());
+            Ok(quote! { #acc.#prop })
+        }
+    }
+
+    /// A `Program.NAME` reference to a constant of the program class.
+    pub fn get_constant(&self, expr: &Expr) -> Option<&ProgramConstant> {
+        let member = expr.as_member()?;
+        if member.obj.as_ident()?.sym != self.program_name.as_str() {
+            return None;
+        }
+        let prop = member.prop.as_ident()?.sym.as_ref();
+        self.constants.iter().find(|c| c.name == prop)
+    }
+
+    /// `name: Type` for the `#[instruction(...)]` attribute of the accounts struct.
+    fn instruction_attribute(&self, name: &Ident) -> TokenStream {
+        let of_type = &self
+            .args
+            .iter()
+            .find(|a| name == a.name.as_str())
+            .expect("instruction argument not found")
+            .of_type;
+        quote! { #name : #of_type }
+    }
+
+    /// Lowers a pubkey expression used in an account constraint such as `address` or `owner`.
+    pub fn get_rs_pubkey_from_ts_expr(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ts_expr: &Expr,
+    ) -> Result<TokenStream> {
+        if let Some(constant) = self.get_constant(ts_expr) {
+            let name = constant.ident();
+            return Ok(quote! { #name });
+        }
+        match ts_expr {
+            Expr::New(new_expr) => {
+                let callee = new_expr.callee.as_ident().ok_or(PoseidonError::IdentNotFound)?;
+                if callee.sym != "Pubkey" {
+                    return Err(anyhow!("expected new Pubkey(\"...\")"));
+                }
+                let key = match new_expr
+                    .args
+                    .as_ref()
+                    .and_then(|args| args.first())
+                    .map(|arg| arg.expr.as_ref())
+                {
+                    Some(Expr::Lit(Lit::Str(s))) => s.value.to_string(),
+                    _ => return Err(anyhow!("expected new Pubkey(\"...\")")),
+                };
+                let key = Literal::string(&key);
+                Ok(quote! { pubkey!(#key) })
+            }
+            Expr::Ident(ident) if ident.sym == "PROGRAM_ID" => Ok(quote! { crate::ID }),
+            Expr::Ident(ident) => {
+                let key = Ident::new(&ident.sym.as_ref().to_case(Case::Snake), Span::call_site());
+                // a `Pubkey` argument, which constraints read through `#[instruction(...)]`,
+                // otherwise an account
+                if self.args.iter().any(|a| key == a.name.as_str()) {
+                    let attribute = self.instruction_attribute(&key);
+                    self.add_instruction_attributes(vec![attribute]);
+                    Ok(quote! { #key })
+                } else {
+                    Ok(quote! { #key.key() })
+                }
+            }
+            Expr::Member(member) => {
+                let obj = member
+                    .obj
+                    .as_ident()
+                    .ok_or(PoseidonError::IdentNotFound)?
+                    .sym
+                    .as_ref();
+                let prop = member
+                    .prop
+                    .as_ident()
+                    .ok_or(PoseidonError::IdentNotFound)?
+                    .sym
+                    .as_ref();
+                match (obj, prop) {
+                    ("SystemProgram", "PROGRAM_ID") => Ok(quote! { system_program::ID }),
+                    ("TokenProgram", "PROGRAM_ID") => Ok(quote! { anchor_spl::token::ID }),
+                    ("TokenProgram2022", "PROGRAM_ID") => Ok(quote! { anchor_spl::token_2022::ID }),
+                    ("MetadataProgram", "PROGRAM_ID") => Ok(quote! { anchor_spl::metadata::ID }),
+                    (_, "PROGRAM_ID") if obj == program_mod.name => Ok(quote! { crate::ID }),
+                    (_, "key") => {
+                        let obj_ident = Ident::new(&obj.to_case(Case::Snake), Span::call_site());
+                        Ok(quote! { #obj_ident.key() })
+                    }
+                    _ => {
+                        let obj_ident = Ident::new(&obj.to_case(Case::Snake), Span::call_site());
+                        let prop_ident = Ident::new(&prop.to_case(Case::Snake), Span::call_site());
+                        Ok(quote! { #obj_ident.#prop_ident })
+                    }
+                }
+            }
+            _ => Err(anyhow!("unsupported pubkey expression")),
+        }
+    }
+
+    /// Lowers a pubkey expression used in the instruction body, where accounts are read through
+    /// `ctx.accounts`.
+    pub fn get_rs_pubkey_in_body(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &HashMap<String, InstructionAccount>,
+        ts_expr: &Expr,
+    ) -> Result<TokenStream> {
+        if let Some(remaining_account) = self.get_rs_remaining_account_from_ts_expr(ts_expr)? {
+            return Ok(quote! { #remaining_account.key() });
+        }
+        if let Expr::Member(m) = ts_expr {
+            if m.prop.as_ident().is_some_and(|p| p.sym == "key") {
+                if let Some(remaining_account) = self.get_rs_remaining_account_from_ts_expr(&m.obj)? {
+                    return Ok(quote! { #remaining_account.key() });
+                }
+            }
+        }
+        match ts_expr {
+            Expr::Ident(ident) if ix_accounts.contains_key(ident.sym.as_ref()) => {
+                let acc = self.get_rs_account(ident.sym.as_ref());
+                Ok(quote! { #acc.key() })
+            }
+            Expr::Ident(ident) if ident.sym != "PROGRAM_ID" => {
+                let arg = Ident::new(&ident.sym.as_ref().to_case(Case::Snake), Span::call_site());
+                Ok(quote! { #arg })
+            }
+            Expr::Member(m)
+                if m.obj
+                    .as_ident()
+                    .is_some_and(|o| ix_accounts.contains_key(o.sym.as_ref())) =>
+            {
+                let obj = Ident::new(
+                    &m.obj
+                        .as_ident()
+                        .ok_or(PoseidonError::IdentNotFound)?
+                        .sym
+                        .as_ref()
+                        .to_case(Case::Snake),
+                    Span::call_site(),
+                );
+                let prop = m.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                if prop == "key" {
+                    let acc = self.get_rs_account(&obj.to_string());
+                    Ok(quote! { #acc.key() })
+                } else {
+                    let prop = Ident::new(&prop.to_case(Case::Snake), Span::call_site());
+                    self.account_field(&obj, &prop)
+                }
+            }
+            _ => self.get_rs_pubkey_from_ts_expr(program_mod, ts_expr),
+        }
+    }
+
+    /// Lowers `new Instruction(programId, keys, data)` into a `solana_program` instruction. Each
+    /// key is written like web3.js as `{ pubkey, isSigner, isWritable }`.
+    pub fn get_rs_instruction_from_ts_expr(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &mut HashMap<String, InstructionAccount>,
+        ts_expr: &Expr,
+    ) -> Result<TokenStream> {
+        let new_expr = ts_expr
+            .as_new()
+            .filter(|n| n.callee.as_ident().is_some_and(|c| c.sym == "Instruction"))
+            .ok_or(anyhow!("expected new Instruction(programId, keys, data)"))?;
+        let args = match new_expr.args.as_deref() {
+            Some(args) if args.len() == 3 => args,
+            _ => return Err(anyhow!("expected new Instruction(programId, keys, data)")),
+        };
+        let program_id = self.get_rs_pubkey_in_body(program_mod, ix_accounts, &args[0].expr)?;
+        let keys = args[1]
+            .expr
+            .as_array()
+            .ok_or(anyhow!("expected the instruction keys as an array"))?;
+        let mut metas: Vec<TokenStream> = vec![];
+        for key in keys.elems.iter().flatten() {
+            let object = key
+                .expr
+                .as_object()
+                .ok_or(anyhow!("expected {{ pubkey, isSigner, isWritable }}"))?;
+            let mut pubkey: Option<&Expr> = None;
+            let (mut is_signer, mut is_writable): (Option<&Expr>, Option<&Expr>) = (None, None);
+            for prop in object.props.iter() {
+                let kv = prop
+                    .as_prop()
+                    .and_then(|p| p.as_key_value())
+                    .ok_or(anyhow!("expected {{ pubkey, isSigner, isWritable }}"))?;
+                let field = kv.key.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                match field {
+                    "pubkey" => pubkey = Some(kv.value.as_ref()),
+                    "isSigner" => is_signer = Some(kv.value.as_ref()),
+                    "isWritable" => is_writable = Some(kv.value.as_ref()),
+                    _ => return Err(anyhow!("unknown instruction key field {}", field)),
+                }
+            }
+            let pubkey = pubkey.ok_or(anyhow!("instruction key without pubkey"))?;
+            let (is_signer, is_writable) = (
+                is_signer.ok_or(anyhow!("instruction key without isSigner"))?,
+                is_writable.ok_or(anyhow!("instruction key without isWritable"))?,
+            );
+            // an account that may be written to has to be mutable in the accounts struct
+            if !matches!(is_writable, Expr::Lit(Lit::Bool(b)) if !b.value) {
+                let acc = match pubkey {
+                    Expr::Ident(i) => Some(i.sym.as_ref()),
+                    Expr::Member(m) => m.obj.as_ident().map(|o| o.sym.as_ref()),
+                    _ => None,
+                };
+                if let Some(acc) = acc.and_then(|a| ix_accounts.get_mut(a)) {
+                    acc.is_mut = true;
+                }
+            }
+            let pubkey = self.get_rs_pubkey_in_body(program_mod, ix_accounts, pubkey)?;
+            let is_signer = match is_signer {
+                Expr::Lit(Lit::Bool(b)) => {
+                    let value = b.value;
+                    quote! { #value }
+                }
+                is_signer => self.get_rs_arg_from_ts_arg(is_signer, false)?,
+            };
+            metas.push(match is_writable {
+                Expr::Lit(Lit::Bool(b)) if b.value => quote! { AccountMeta::new(#pubkey, #is_signer) },
+                Expr::Lit(Lit::Bool(_)) => quote! { AccountMeta::new_readonly(#pubkey, #is_signer) },
+                is_writable => {
+                    let is_writable = self.get_rs_arg_from_ts_arg(is_writable, false)?;
+                    quote! {
+                        AccountMeta {
+                            pubkey: #pubkey,
+                            is_signer: #is_signer,
+                            is_writable: #is_writable,
+                        }
+                    }
+                }
+            });
+        }
+        let data = match args[2].expr.as_ref() {
+            Expr::Array(bytes) => {
+                let mut bytes_token: Vec<TokenStream> = vec![];
+                for byte in bytes.elems.iter().flatten() {
+                    bytes_token.push(match byte.expr.as_ref() {
+                        Expr::Lit(lit @ Lit::Num(_)) => rs_int_literal(lit, Some("u8"))?,
+                        byte_expr => self.get_rs_arg_from_ts_arg(byte_expr, false)?,
+                    });
+                }
+                quote! { vec![#(#bytes_token),*] }
+            }
+            data_expr => {
+                let data = self.get_rs_arg_from_ts_arg(data_expr, false)?;
+                quote! { #data.to_vec() }
+            }
+        };
+        Ok(quote! {
+            anchor_lang::solana_program::instruction::Instruction {
+                program_id: #program_id,
+                // not `vec![]`, which prettyplease leaves unformatted
+                accounts: Vec::from([#(#metas),*]),
+                data: #data,
+            }
+        })
+    }
+
+    /// Whether `expr` is a remaining account, either `Context.remainingAccounts[i]` or a loop
+    /// variable over them.
+    pub fn is_remaining_account(&self, ts_expr: &Expr) -> bool {
+        match ts_expr {
+            Expr::Ident(i) => self.loop_accounts.iter().any(|a| i.sym == *a),
+            Expr::Member(m) => m.prop.is_computed() && is_remaining_accounts(&m.obj),
+            _ => false,
+        }
+    }
+
+    /// Lowers `Context.remainingAccounts[i]` into a checked lookup in `ctx.remaining_accounts`
+    /// that fails with `AccountNotEnoughKeys` when too few accounts were passed, or returns
+    /// `None` for any other expression.
+    pub fn get_rs_remaining_account_from_ts_expr(
+        &mut self,
+        ts_expr: &Expr,
+    ) -> Result<Option<TokenStream>> {
+        if !self.is_remaining_account(ts_expr) {
+            return Ok(None);
+        }
+        let Expr::Member(m) = ts_expr else {
+            let acc = ts_expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+            let acc = Ident::new(&acc.to_case(Case::Snake), Span::call_site());
+            return Ok(Some(quote! { #acc }));
+        };
+        let index = self.get_rs_index_from_ts_expr(m)?;
+        self.uses_remaining_accounts = true;
+        Ok(Some(quote! {
+            ctx.remaining_accounts.get(#index).ok_or(ErrorCode::AccountNotEnoughKeys)?
+        }))
+    }
+
+    /// Lowers the index of `x[i]` into a `usize`.
+    pub fn get_rs_index_from_ts_expr(&mut self, m: &MemberExpr) -> Result<TokenStream> {
+        let index = m.prop.as_computed().ok_or(PoseidonError::ExprNotFound)?;
+        match index.expr.as_ref() {
+            Expr::Lit(lit @ Lit::Num(_)) => {
+                let index_literal = rs_int_literal(lit, Some("usize"))?;
+                Ok(quote! { #index_literal })
+            }
+            index_expr => {
+                let index = self.get_rs_arg_from_ts_arg(index_expr, false)?;
+                Ok(quote! { #index as usize })
+            }
+        }
+    }
+
+    /// Lowers an account passed to a CPI, either a named account or a remaining account, into
+    /// its `AccountInfo`.
+    pub fn get_rs_account_info_from_ts_expr(&mut self, ts_expr: &Expr) -> Result<TokenStream> {
+        if let Some(remaining_account) = self.get_rs_remaining_account_from_ts_expr(ts_expr)? {
+            return Ok(quote! { #remaining_account.to_account_info() });
+        }
+        let acc = self.get_rs_account(ts_expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref());
+        Ok(quote! { #acc.to_account_info() })
+    }
+
+    /// Lowers an expression of the instruction body, such as the right-hand side of an
+    /// assignment. Operators keep the grouping of the TypeScript expression, and the method
+    /// helpers (`a.add(b)`, `a.lt(b)`, ...) accept any operand.
+    pub fn get_rs_expr_from_ts_expr(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &HashMap<String, InstructionAccount>,
+        custom_accounts: &HashMap<String, ProgramAccount>,
+        ts_expr: &Expr,
+    ) -> Result<TokenStream> {
+        if let Some(collection) =
+            self.get_rs_collection_expr(program_mod, ix_accounts, custom_accounts, ts_expr)?
+        {
+            return Ok(collection);
+        }
+        let expr = match ts_expr {
+            Expr::Paren(p) => {
+                let inner =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, &p.expr)?;
+                quote! { (#inner) }
+            }
+            Expr::Unary(u) => {
+                let mut arg =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, &u.arg)?;
+                // `!a.key.equals(b.key)` negates the whole comparison
+                if is_helper_call(&u.arg) {
+                    arg = quote! { (#arg) };
+                }
+                match u.op {
+                    // `!` negates a bool and `~` flips the bits of an integer, both are `!` in Rust
+                    UnaryOp::Bang | UnaryOp::Tilde => quote! { !#arg },
+                    UnaryOp::Minus => quote! { -#arg },
+                    op => return Err(anyhow!("unsupported unary operator {}", op)),
+                }
+            }
+            Expr::Bin(b) => {
+                let mut left =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, &b.left)?;
+                let mut right =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, &b.right)?;
+                if needs_parens(b.op, &b.left) {
+                    left = quote! { (#left) };
+                }
+                if needs_parens(b.op, &b.right) {
+                    right = quote! { (#right) };
+                }
+                let op = rs_binary_op(b.op)?;
+                quote! { #left #op #right }
+            }
+            Expr::Call(c) if cast_call(ts_expr).is_some() => {
+                let (value, to) = cast_call(ts_expr).ok_or(PoseidonError::CallNotFound)?;
+                let value =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, value)?;
+                rs_cast(value, self.casts.get(&c.span), to)
+            }
+            Expr::Call(c) if is_helper_call(ts_expr) => {
+                let callee = c
+                    .callee
+                    .as_expr()
+                    .and_then(|callee| callee.as_member())
+                    .ok_or(PoseidonError::MemberNotFound)?;
+                let method = callee.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let op = helper_op(method).ok_or(anyhow!("unsupported method {}", method))?;
+                let right_expr = &c
+                    .args
+                    .first()
+                    .ok_or(anyhow!("{} expects an argument", method))?
+                    .expr;
+                let mut left =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, &callee.obj)?;
+                let mut right =
+                    self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, right_expr)?;
+                // the method chain fixes the evaluation order, keep it when nesting operators
+                if callee.obj.is_bin() || is_helper_call(&callee.obj) {
+                    left = quote! { (#left) };
+                }
+                if right_expr.is_bin() || is_helper_call(right_expr) {
+                    right = quote! { (#right) };
+                }
+                quote! { #left #op #right }
+            }
+            Expr::Call(c)
+                if c.callee
+                    .as_expr()
+                    .and_then(|callee| callee.as_member())
+                    .and_then(|m| m.prop.as_ident())
+                    .is_some_and(|p| p.sym == "getBump") =>
+            {
+                let callee = c
+                    .callee
+                    .as_expr()
+                    .and_then(|callee| callee.as_member())
+                    .ok_or(PoseidonError::MemberNotFound)?;
+                let acc = callee.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let acc = Ident::new(&acc.to_case(Case::Snake), Span::call_site());
+                quote! { ctx.bumps.#acc }
+            }
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, None)?,
+            Expr::Lit(Lit::Bool(b)) => {
+                let value = b.value;
+                quote! { #value }
+            }
+            Expr::Lit(Lit::Str(s)) => {
+                let value = Literal::string(&s.value);
+                quote! { #value.to_string() }
+            }
+            Expr::Member(m)
+                if m.prop.as_ident().is_some_and(|p| p.sym == "key")
+                    && m.obj.as_ident().is_some_and(|o| {
+                        ix_accounts.contains_key(o.sym.as_ref()) || self.is_remaining_account(&m.obj)
+                    }) =>
+            {
+                self.get_rs_pubkey_in_body(program_mod, ix_accounts, ts_expr)?
+            }
+            Expr::Member(_) if lamports_account(ix_accounts, custom_accounts, ts_expr).is_some() => {
+                let acc = lamports_account(ix_accounts, custom_accounts, ts_expr)
+                    .ok_or(PoseidonError::IdentNotFound)?;
+                let acc = self.get_rs_account(&acc);
+                quote! { #acc.get_lamports() }
+            }
+            // `new Pubkey("...")` and `SystemProgram.PROGRAM_ID`
+            Expr::New(_) => self.get_rs_pubkey_in_body(program_mod, ix_accounts, ts_expr)?,
+            Expr::Member(m) if m.prop.as_ident().is_some_and(|p| p.sym == "PROGRAM_ID") => {
+                self.get_rs_pubkey_from_ts_expr(program_mod, ts_expr)?
+            }
+            _ => self.get_rs_arg_from_ts_arg(ts_expr, false)?,
+        };
+        Ok(expr)
+    }
+
+    /// Lowers `acc.addLamports(n)` and `acc.subLamports(n)`, which move lamports without a system
+    /// transfer. Anchor checks the new balance for overflow, and only accounts owned by the program
+    /// can be debited. Returns `None` for any other statement.
+    pub fn get_rs_lamports_stmt(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &mut HashMap<String, InstructionAccount>,
+        custom_accounts: &HashMap<String, ProgramAccount>,
+        ts_expr: &Expr,
+    ) -> Result<Option<TokenStream>> {
+        let Some(c) = ts_expr.as_call() else {
+            return Ok(None);
+        };
+        let Some(callee) = c.callee.as_expr().and_then(|e| e.as_member()) else {
+            return Ok(None);
+        };
+        let ts_method = callee.prop.as_ident().map(|p| p.sym.as_ref()).unwrap_or_default();
+        let method = match ts_method {
+            "addLamports" => quote! { add_lamports },
+            "subLamports" => quote! { sub_lamports },
+            _ => return Ok(None),
+        };
+        let acc = callee.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string();
+        if !ix_accounts.contains_key(&acc) {
+            return Err(anyhow!("{} is not an account of the instruction", acc));
+        }
+        let amount = match c.args.first().map(|arg| arg.expr.as_ref()) {
+            Some(Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_)))) => rs_int_literal(lit, Some("u64"))?,
+            Some(amount) => self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, amount)?,
+            None => return Err(anyhow!("{}.{} expects an amount", acc, ts_method)),
+        };
+        if let Some(acc) = ix_accounts.get_mut(&acc) {
+            acc.is_mut = true;
+        }
+        let acc = self.get_rs_account(&acc);
+        Ok(Some(quote! {
+            #acc.#method(#amount)?;
+        }))
+    }
+
+    /// Lowers an element pushed to, searched in or appended to a `Vec` or `Str` field.
+    /// Strings that are stored are cloned, so the argument or field they come from stays usable.
+    fn get_rs_collection_elem(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &HashMap<String, InstructionAccount>,
+        field: &ProgramAccountField,
+        ts_expr: &Expr,
+        is_stored: bool,
+    ) -> Result<TokenStream> {
+        match ts_expr {
+            Expr::Lit(Lit::Str(s)) => {
+                let value = Literal::string(&s.value);
+                match is_stored {
+                    true => Ok(quote! { #value.to_string() }),
+                    false => Ok(quote! { #value }),
+                }
+            }
+            _ if field.of_type == "Vec<Pubkey>" => {
+                self.get_rs_pubkey_in_body(program_mod, ix_accounts, ts_expr)
+            }
+            _ if is_stored && field.of_type == "Vec<Str>" => {
+                let elem = self.get_rs_arg_from_ts_arg(ts_expr, false)?;
+                Ok(quote! { #elem.clone() })
+            }
+            _ => self.get_rs_arg_from_ts_arg(ts_expr, false),
+        }
+    }
+
+    /// Lowers reads of a `Vec` or `Str` account field: `acc.items[i]`, `acc.items.length`,
+    /// `acc.items.includes(x)`, `acc.items.pop()` and `acc.name.concat(s)`. Returns `None` for
+    /// any other expression.
+    pub fn get_rs_collection_expr(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &HashMap<String, InstructionAccount>,
+        custom_accounts: &HashMap<String, ProgramAccount>,
+        ts_expr: &Expr,
+    ) -> Result<Option<TokenStream>> {
+        match ts_expr {
+            Expr::Member(m) => {
+                let field = match collection_field(ix_accounts, custom_accounts, &m.obj) {
+                    Some((_, field)) => field,
+                    None => return Ok(None),
+                };
+                let collection = self.get_rs_arg_from_ts_arg(&m.obj, false)?;
+                if m.prop.is_computed() {
+                    let index = self.get_rs_index_from_ts_expr(m)?;
+                    return Ok(Some(match field.of_type.as_str() {
+                        "Vec<Str>" => quote! { #collection[#index].clone() },
+                        _ => quote! { #collection[#index] },
+                    }));
+                }
+                match m.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref() {
+                    "length" => Ok(Some(quote! { #collection.len() as u64 })),
+                    prop => Err(anyhow!("unsupported property {} of {}", prop, field.name)),
+                }
+            }
+            Expr::Call(c) => {
+                let callee = match c.callee.as_expr().and_then(|e| e.as_member()) {
+                    Some(callee) => callee,
+                    None => return Ok(None),
+                };
+                let field = match collection_field(ix_accounts, custom_accounts, &callee.obj) {
+                    Some((_, field)) => field,
+                    None => return Ok(None),
+                };
+                let method = callee.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let collection = self.get_rs_arg_from_ts_arg(&callee.obj, false)?;
+                let lowered = match (method, c.args.first()) {
+                    ("includes", Some(arg)) => {
+                        let elem = self.get_rs_collection_elem(
+                            program_mod,
+                            ix_accounts,
+                            field,
+                            &arg.expr,
+                            false,
+                        )?;
+                        match field.of_type.as_str() {
+                            // `Vec<String>::contains` takes a `&String`, so compare the elements
+                            // as `&str` with both literals and `String` values
+                            "Vec<Str>" => quote! { #collection.iter().any(|s| s.as_str() == #elem) },
+                            _ => quote! { #collection.contains(&#elem) },
+                        }
+                    }
+                    ("pop", None) if field.of_type != "Str" => {
+                        quote! { #collection.pop().ok_or(ErrorCode::RequireViolated)? }
+                    }
+                    ("concat", Some(arg)) if field.of_type == "Str" => {
+                        let suffix = self.get_rs_collection_elem(
+                            program_mod,
+                            ix_accounts,
+                            field,
+                            &arg.expr,
+                            false,
+                        )?;
+                        match arg.expr.as_ref() {
+                            Expr::Lit(Lit::Str(_)) => quote! { #collection.clone() + #suffix },
+                            _ => quote! { #collection.clone() + &#suffix },
+                        }
+                    }
+                    _ => return Err(anyhow!("unsupported method {} of {}", method, field.name)),
+                };
+                Ok(Some(lowered))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Lowers statements on a `Vec` or `Str` account field: `acc.items.push(x)`,
+    /// `acc.items.pop()`, `acc.items[i] = x` and assignments from the reads of
+    /// `get_rs_collection_expr`. Writes are checked against the `Vec<T, N>`/`Str<N>` capacity the
+    /// account was allocated with. Returns `None` for any other statement.
+    pub fn get_rs_collection_stmt(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &mut HashMap<String, InstructionAccount>,
+        custom_accounts: &HashMap<String, ProgramAccount>,
+        ts_expr: &Expr,
+    ) -> Result<Option<TokenStream>> {
+        // string literals are checked here, anything else when the instruction runs
+        let elem_capacity = |field: &ProgramAccountField, ts_elem: &Expr, elem: &TokenStream| {
+            match (field.of_type.as_str(), ts_elem) {
+                ("Vec<Str>", Expr::Lit(Lit::Str(s))) if s.value.len() > field.max_len[1] as usize => {
+                    Err(anyhow!("\"{}\" is longer than the {} bytes of {}", s.value, field.max_len[1], field.name))
+                }
+                ("Vec<Str>", Expr::Lit(Lit::Str(_))) => Ok(quote! {}),
+                ("Vec<Str>", _) => {
+                    let capacity = Literal::u32_unsuffixed(field.max_len[1]);
+                    Ok(quote! { require_gte!(#capacity, #elem.len()); })
+                }
+                _ => Ok(quote! {}),
+            }
+        };
+        match ts_expr {
+            Expr::Call(c) => {
+                let callee = match c.callee.as_expr().and_then(|e| e.as_member()) {
+                    Some(callee) => callee,
+                    None => return Ok(None),
+                };
+                let (acc, field) = match collection_field(ix_accounts, custom_accounts, &callee.obj) {
+                    Some(collection) => collection,
+                    None => return Ok(None),
+                };
+                let method = callee.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let collection = self.get_rs_arg_from_ts_arg(&callee.obj, false)?;
+                let lowered = match (method, c.args.first()) {
+                    ("push", Some(arg)) if field.of_type != "Str" => {
+                        let elem = self.get_rs_collection_elem(
+                            program_mod,
+                            ix_accounts,
+                            field,
+                            &arg.expr,
+                            true,
+                        )?;
+                        let elem_ref = self.get_rs_collection_elem(
+                            program_mod,
+                            ix_accounts,
+                            field,
+                            &arg.expr,
+                            false,
+                        )?;
+                        let capacity = Literal::u32_unsuffixed(field.max_len[0]);
+                        let elem_check = elem_capacity(field, &arg.expr, &elem_ref)?;
+                        quote! {
+                            require_gt!(#capacity, #collection.len());
+                            #elem_check
+                            #collection.push(#elem);
+                        }
+                    }
+                    ("pop", None) if field.of_type != "Str" => quote! { #collection.pop(); },
+                    _ => return Err(anyhow!("unsupported method {} of {}", method, field.name)),
+                };
+                if let Some(acc) = ix_accounts.get_mut(&acc) {
+                    acc.is_mut = true;
+                }
+                Ok(Some(lowered))
+            }
+            Expr::Assign(a) => {
+                let left_expr = match a.left.as_expr().and_then(|e| e.as_member()) {
+                    Some(left) => left,
+                    None => return Ok(None),
+                };
+                // `acc.items[i] = x`
+                if left_expr.prop.is_computed() {
+                    let (acc, field) =
+                        match collection_field(ix_accounts, custom_accounts, &left_expr.obj) {
+                            Some(collection) => collection,
+                            None => return Ok(None),
+                        };
+                    let collection = self.get_rs_arg_from_ts_arg(&left_expr.obj, false)?;
+                    let index = self.get_rs_index_from_ts_expr(left_expr)?;
+                    let elem =
+                        self.get_rs_collection_elem(program_mod, ix_accounts, field, &a.right, true)?;
+                    let elem_ref =
+                        self.get_rs_collection_elem(program_mod, ix_accounts, field, &a.right, false)?;
+                    let elem_check = elem_capacity(field, &a.right, &elem_ref)?;
+                    if let Some(acc) = ix_accounts.get_mut(&acc) {
+                        acc.is_mut = true;
+                    }
+                    return Ok(Some(quote! {
+                        #elem_check
+                        #collection[#index] = #elem;
+                    }));
+                }
+                let right = match self.get_rs_collection_expr(
+                    program_mod,
+                    ix_accounts,
+                    custom_accounts,
+                    &a.right,
+                )? {
+                    Some(right) => right,
+                    None => return Ok(None),
+                };
+                let left_obj = left_expr.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string();
+                let left_prop = left_expr.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let left = self.account_field(
+                    &Ident::new(&left_obj.to_case(Case::Snake), Span::call_site()),
+                    &Ident::new(&left_prop.to_case(Case::Snake), Span::call_site()),
+                )?;
+                // a `Str` that grows with `concat` must still fit in its account
+                let left_field = a.left.as_expr().ok_or(PoseidonError::ExprNotFound)?;
+                let capacity_check = match collection_field(ix_accounts, custom_accounts, left_field) {
+                    Some((_, field)) if field.of_type == "Str" => {
+                        let capacity = Literal::u32_unsuffixed(field.max_len[0]);
+                        quote! { require_gte!(#capacity, #left.len()); }
+                    }
+                    _ => quote! {},
+                };
+                if let Some(acc) = ix_accounts.get_mut(&left_obj) {
+                    acc.is_mut = true;
+                }
+                Ok(Some(quote! {
+                    #left = #right;
+                    #capacity_check
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Lowers the test of an `if`. `if (x)` on an optional account or argument becomes
+    /// `if let Some(x) = ...`, which also returns the account it binds, and any other test is
+    /// lowered as a boolean expression. Unlike JavaScript truthiness, `if (x)` on an optional
+    /// argument is true for `Some(0)`: it checks that the argument was passed, not its value.
+    pub fn get_rs_if_header(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &HashMap<String, InstructionAccount>,
+        custom_accounts: &HashMap<String, ProgramAccount>,
+        test: &Expr,
+    ) -> Result<(TokenStream, Option<String>)> {
+        if let Expr::Ident(ident) = test {
+            let name = ident.sym.as_ref();
+            let name_ident = Ident::new(&name.to_case(Case::Snake), Span::call_site());
+            if let Some(acc) = ix_accounts.get(name).filter(|acc| acc.optional) {
+                return Ok((
+                    quote! { if let Some(#name_ident) = ctx.accounts.#name_ident.as_mut() },
+                    Some(acc.name.clone()),
+                ));
+            }
+            if self.args.iter().any(|a| a.optional && name_ident == a.name) {
+                return Ok((quote! { if let Some(#name_ident) = #name_ident }, None));
+            }
+        }
+        let test = self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, test)?;
+        Ok((quote! { if #test }, None))
+    }
+
+    /// Lowers the header of a bounded `for` or `for...of` loop into what follows `for` in Rust:
+    /// `i in start..end` for a counter, `x in iterable` otherwise.
+    pub fn get_rs_loop_header(&mut self, loop_stmt: &Stmt) -> Result<TokenStream> {
+        match loop_stmt {
+            Stmt::For(f) => {
+                let unsupported = || anyhow!("only `for (let i = start; i < end; i++)` loops are supported");
+                let counter_decl = match &f.init {
+                    Some(VarDeclOrExpr::VarDecl(v)) if v.decls.len() == 1 => &v.decls[0],
+                    _ => return Err(unsupported()),
+                };
+                let counter = counter_decl.name.as_ident().ok_or(unsupported())?.id.sym.as_ref();
+                let start = self.get_rs_loop_bound(counter_decl.init.as_deref().ok_or(unsupported())?)?;
+                let test = f.test.as_deref().and_then(|t| t.as_bin()).ok_or(unsupported())?;
+                if test.left.as_ident().map(|l| l.sym.as_ref()) != Some(counter) {
+                    return Err(unsupported());
+                }
+                let end = self.get_rs_loop_bound(&test.right)?;
+                let counts_up_by_one = match f.update.as_deref() {
+                    Some(Expr::Update(u)) => {
+                        u.op == UpdateOp::PlusPlus && u.arg.as_ident().is_some_and(|a| a.sym == counter)
+                    }
+                    Some(Expr::Assign(a)) => {
+                        a.op == AssignOp::AddAssign
+                            && a.left.as_ident().is_some_and(|l| l.sym == counter)
+                            && matches!(a.right.as_ref(), Expr::Lit(Lit::Num(n)) if n.value == 1.0)
+                    }
+                    _ => false,
+                };
+                if !counts_up_by_one {
+                    return Err(unsupported());
+                }
+                let counter = Ident::new(&counter.to_case(Case::Snake), Span::call_site());
+                match test.op {
+                    BinaryOp::Lt => Ok(quote! { #counter in #start..#end }),
+                    BinaryOp::LtEq => Ok(quote! { #counter in #start..=#end }),
+                    _ => Err(unsupported()),
+                }
+            }
+            Stmt::ForOf(f) => {
+                let item = match &f.left {
+                    ForHead::VarDecl(v) if v.decls.len() == 1 => v.decls[0]
+                        .name
+                        .as_ident()
+                        .ok_or(PoseidonError::IdentNotFound)?
+                        .id
+                        .sym
+                        .to_string(),
+                    _ => return Err(anyhow!("expected `for (const item of ...)`")),
+                };
+                let item_ident = Ident::new(&item.to_case(Case::Snake), Span::call_site());
+                if is_remaining_accounts(&f.right) {
+                    self.uses_remaining_accounts = true;
+                    self.loop_accounts.push(item);
+                    return Ok(quote! { #item_ident in ctx.remaining_accounts.iter() });
+                }
+                // iterate over a copy, so the loop body can still mutate the accounts
+                let iterable = self.get_rs_arg_from_ts_arg(&f.right, false)?;
+                Ok(quote! { #item_ident in #iterable.clone() })
+            }
+            _ => Err(anyhow!("expected a for loop")),
+        }
+    }
+
+    fn get_rs_loop_bound(&mut self, bound: &Expr) -> Result<TokenStream> {
+        match bound {
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, Some("u64")),
+            bound_expr => self.get_rs_arg_from_ts_arg(bound_expr, false),
+        }
+    }
+
+    /// Lowers an account passed to a mint extension, either as `acc` or `acc.key`.
+    pub fn get_rs_account_from_ts_expr(&self, ts_expr: &Expr) -> Result<Ident> {
+        let acc = match ts_expr {
+            Expr::Ident(i) => i.sym.as_ref(),
+            Expr::Member(m) => m.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref(),
+            _ => return Err(anyhow!("expected an account or its key")),
+        };
+        Ok(Ident::new(&acc.to_case(Case::Snake), Span::call_site()))
+    }
+
+    /// Lowers a custom error such as `EscrowError.WrongMaker` into `EscrowError::WrongMaker`.
+    pub fn get_rs_error_from_ts_expr(&self, ts_expr: &Expr) -> Result<TokenStream> {
+        let members = ts_expr.as_member().ok_or(PoseidonError::MemberNotFound)?;
+        let error_enum = Ident::new(
+            members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref(),
+            Span::call_site(),
+        );
+        let variant = Ident::new(
+            members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref(),
+            Span::call_site(),
+        );
+        Ok(quote! { #error_enum::#variant })
+    }
+
+    /// Lowers a sysvar read such as `Clock.get().unixTimestamp` or `Rent.get().minimumBalance(n)`,
+    /// or returns `None` for any other expression.
+    pub fn get_rs_sysvar_from_ts_expr(
+        &mut self,
+        ts_expr: &Expr,
+        is_account_struct: bool,
+    ) -> Result<Option<TokenStream>> {
+        // `Sysvar.get()` followed by a field, or by a method call with its arguments
+        let (getter, method_args) = match ts_expr {
+            Expr::Member(m) => (m, None),
+            Expr::Call(c) => match c.callee.as_expr().and_then(|e| e.as_member()) {
+                Some(m) => (m, Some(&c.args)),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let Some(sysvar) = getter
+            .obj
+            .as_call()
+            .and_then(|c| c.callee.as_expr())
+            .and_then(|e| e.as_member())
+            .filter(|m| m.prop.as_ident().is_some_and(|p| p.sym == "get"))
+            .and_then(|m| m.obj.as_ident())
+            .map(|i| i.sym.as_ref())
+            .filter(|s| ["Clock", "Rent", "EpochSchedule"].contains(s))
+        else {
+            return Ok(None);
+        };
+        let member = getter.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+        let is_known = match (sysvar, method_args.is_some()) {
+            ("Clock", false) => [
+                "slot",
+                "epochStartTimestamp",
+                "epoch",
+                "leaderScheduleEpoch",
+                "unixTimestamp",
+            ]
+            .contains(&member),
+            ("Rent", false) => ["lamportsPerByteYear", "exemptionThreshold", "burnPercent"].contains(&member),
+            ("Rent", true) => member == "minimumBalance",
+            ("EpochSchedule", false) => [
+                "slotsPerEpoch",
+                "leaderScheduleSlotOffset",
+                "warmup",
+                "firstNormalEpoch",
+                "firstNormalSlot",
+            ]
+            .contains(&member),
+            ("EpochSchedule", true) => {
+                ["getEpoch", "getFirstSlotInEpoch", "getLastSlotInEpoch"].contains(&member)
+            }
+            _ => false,
+        };
+        if !is_known {
+            return Err(anyhow!("{}.get().{} is not supported", sysvar, member));
+        }
+        let sysvar_ident = Ident::new(sysvar, Span::call_site());
+        let member_ident = Ident::new(&member.to_case(Case::Snake), Span::call_site());
+        let Some(method_args) = method_args else {
+            return Ok(Some(quote! { #sysvar_ident::get()?.#member_ident }));
+        };
+        let arg = &method_args
+            .first()
+            .ok_or(anyhow!("{}.get().{} expects an argument", sysvar, member))?
+            .expr;
+        let arg = match arg.as_ref() {
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, Some("u64"))?,
+            // `minimum_balance` takes the account size as `usize`
+            arg_expr if member == "minimumBalance" => {
+                let arg = self.get_rs_arg_from_ts_arg(arg_expr, is_account_struct)?;
+                quote! { #arg as usize }
+            }
+            arg_expr => self.get_rs_arg_from_ts_arg(arg_expr, is_account_struct)?,
+        };
+        Ok(Some(quote! { #sysvar_ident::get()?.#member_ident(#arg) }))
+    }
+
+    /// Lowers the boolean expression passed to `.constraint(...)`. Accounts are referenced by
+    /// name as in any other `#[account(...)]` constraint, and instruction arguments used in the
+    /// expression are added to `#[instruction(...)]`.
+    pub fn get_rs_constraint_from_ts_expr(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ts_expr: &Expr,
+    ) -> Result<TokenStream> {
+        if let Some(sysvar) = self.get_rs_sysvar_from_ts_expr(ts_expr, true)? {
+            return Ok(sysvar);
         }
+        if let Some(constant) = self.get_constant(ts_expr) {
+            let name = constant.ident();
+            return Ok(quote! { #name });
+        }
+        let constraint = match ts_expr {
+            Expr::Paren(p) => {
+                let inner = self.get_rs_constraint_from_ts_expr(program_mod, &p.expr)?;
+                quote! { (#inner) }
+            }
+            Expr::Unary(u) => {
+                let mut arg = self.get_rs_constraint_from_ts_expr(program_mod, &u.arg)?;
+                if is_helper_call(&u.arg) {
+                    arg = quote! { (#arg) };
+                }
+                match u.op {
+                    UnaryOp::Bang | UnaryOp::Tilde => quote! { !#arg },
+                    UnaryOp::Minus => quote! { -#arg },
+                    _ => return Err(anyhow!("unsupported unary operator in constraint")),
+                }
+            }
+            Expr::Bin(b) => {
+                let mut left = self.get_rs_constraint_from_ts_expr(program_mod, &b.left)?;
+                let mut right = self.get_rs_constraint_from_ts_expr(program_mod, &b.right)?;
+                if needs_parens(b.op, &b.left) {
+                    left = quote! { (#left) };
+                }
+                if needs_parens(b.op, &b.right) {
+                    right = quote! { (#right) };
+                }
+                let op = rs_binary_op(b.op)?;
+                quote! { #left #op #right }
+            }
+            Expr::Call(c) if cast_call(ts_expr).is_some() => {
+                let (value, to) = cast_call(ts_expr).ok_or(PoseidonError::CallNotFound)?;
+                let value = self.get_rs_constraint_from_ts_expr(program_mod, value)?;
+                rs_cast(value, self.casts.get(&c.span), to)
+            }
+            Expr::Call(c) => {
+                let members = c
+                    .callee
+                    .as_expr()
+                    .ok_or(PoseidonError::ExprNotFound)?
+                    .as_member()
+                    .ok_or(PoseidonError::MemberNotFound)?;
+                let method = members
+                    .prop
+                    .as_ident()
+                    .ok_or(PoseidonError::IdentNotFound)?
+                    .sym
+                    .as_ref();
+                let (op, is_arithmetic) = match method {
+                    "add" => (quote! { + }, true),
+                    "sub" => (quote! { - }, true),
+                    "mul" => (quote! { * }, true),
+                    "div" => (quote! { / }, true),
+                    "eq" => (quote! { == }, false),
+                    "neq" => (quote! { != }, false),
+                    "lt" => (quote! { < }, false),
+                    "lte" => (quote! { <= }, false),
+                    "gt" => (quote! { > }, false),
+                    "gte" => (quote! { >= }, false),
+                    "equals" => (quote! { == }, false),
+                    _ => return Err(anyhow!("unsupported method {} in constraint", method)),
+                };
+                let mut left = self.get_rs_constraint_from_ts_expr(program_mod, &members.obj)?;
+                let right_expr = &c
+                    .args
+                    .first()
+                    .ok_or(anyhow!("{} expects an argument", method))?
+                    .expr;
+                let mut right = self.get_rs_constraint_from_ts_expr(program_mod, right_expr)?;
+                // the method chain fixes the evaluation order, keep it when nesting arithmetic
+                if is_arithmetic {
+                    if members.obj.is_call() {
+                        left = quote! { (#left) };
+                    }
+                    if right_expr.is_call() {
+                        right = quote! { (#right) };
+                    }
+                }
+                quote! { #left #op #right }
+            }
+            Expr::Member(m) => {
+                let obj = m.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let prop = m.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                let obj_ident = Ident::new(&obj.to_case(Case::Snake), Span::call_site());
+                let prop_ident = Ident::new(&prop.to_case(Case::Snake), Span::call_site());
+                if prop == "key" {
+                    quote! { #obj_ident.key() }
+                } else if self.struct_args.contains_key(&obj_ident.to_string()) {
+                    let attribute = self.instruction_attribute(&obj_ident);
+                    self.add_instruction_attributes(vec![attribute]);
+                    quote! { #obj_ident.#prop_ident }
+                } else if self.zero_copy_accounts.contains(&obj_ident.to_string()) {
+                    quote! { #obj_ident.load()?.#prop_ident }
+                } else {
+                    quote! { #obj_ident.#prop_ident }
+                }
+            }
+            Expr::Ident(_) => self.get_rs_arg_from_ts_arg(ts_expr, true)?,
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, None)?,
+            Expr::Lit(Lit::Bool(b)) => {
+                let value = b.value;
+                quote! { #value }
+            }
+            Expr::New(_) => self.get_rs_pubkey_from_ts_expr(program_mod, ts_expr)?,
+            _ => return Err(anyhow!("unsupported expression in constraint")),
+        };
+        Ok(constraint)
     }
 
     pub fn get_rs_arg_from_ts_arg(
@@ -58,9 +1519,32 @@ impl ProgramInstruction {
         ts_arg_expr: &Expr,
         is_account_struct: bool,
     ) -> Result<TokenStream> {
+        if let Some(sysvar) = self.get_rs_sysvar_from_ts_expr(ts_arg_expr, is_account_struct)? {
+            return Ok(sysvar);
+        }
+        if let Some(constant) = self.get_constant(ts_arg_expr) {
+            let name = constant.ident();
+            return Ok(quote! { #name });
+        }
         let ts_arg: TokenStream;
         let mut ix_attribute_token: Vec<TokenStream> = vec![];
         match ts_arg_expr {
+            Expr::Member(m)
+                if is_remaining_accounts(&m.obj)
+                    && m.prop.as_ident().is_some_and(|p| p.sym == "length") =>
+            {
+                self.uses_remaining_accounts = true;
+                ts_arg = quote! { ctx.remaining_accounts.len() as u64 };
+            }
+            Expr::Member(m) if m.prop.as_ident().is_some_and(|p| p.sym == "length") => {
+                let vec = self.get_rs_arg_from_ts_arg(&m.obj, false)?;
+                ts_arg = quote! { #vec.len() as u64 };
+            }
+            Expr::Member(m) if m.prop.is_computed() && !is_remaining_accounts(&m.obj) => {
+                let vec = self.get_rs_arg_from_ts_arg(&m.obj, false)?;
+                let index = self.get_rs_index_from_ts_expr(m)?;
+                ts_arg = quote! { #vec[#index] };
+            }
             Expr::Member(m) => {
                 let ts_arg_obj = m
                     .obj
@@ -90,14 +1574,17 @@ impl ProgramInstruction {
                 // } else {
                 //     panic!("{:#?} not provided in proper format", ts_arg_expr)
                 // }
-                if is_account_struct {
+                if is_account_struct && self.struct_args.contains_key(&ts_arg_obj_ident.to_string()) {
+                    ix_attribute_token.push(self.instruction_attribute(&ts_arg_obj_ident));
                     ts_arg = quote! {
-                        #ts_arg_obj_ident
+                        #ts_arg_obj_ident.#ts_arg_prop_ident
                     };
-                } else {
+                } else if is_account_struct {
                     ts_arg = quote! {
-                        ctx.accounts.#ts_arg_obj_ident.#ts_arg_prop_ident
+                        #ts_arg_obj_ident
                     };
+                } else {
+                    ts_arg = self.account_field(&ts_arg_obj_ident, &ts_arg_prop_ident)?;
                 }
             }
             Expr::Ident(i) => {
@@ -108,7 +1595,7 @@ impl ProgramInstruction {
                 );
                 if is_account_struct {
                     for arg in self.args.iter() {
-                        if arg.name == ts_arg_str {
+                        if arg.name == ts_arg_str.to_case(Case::Snake) {
                             let type_ident = &arg.of_type;
 
                             ix_attribute_token.push(quote! {
@@ -121,18 +1608,42 @@ impl ProgramInstruction {
                     #ts_arg_ident
                 };
             }
-            Expr::Lit(Lit::Num(literal_value)) => {
-                let literal_token = Literal::u8_unsuffixed(literal_value.value as u8);
-
-                ts_arg = quote! {#literal_token}
+            Expr::Paren(p) => {
+                let inner = self.get_rs_arg_from_ts_arg(&p.expr, is_account_struct)?;
+                ts_arg = quote! { (#inner) };
+            }
+            Expr::Unary(u) if matches!(u.op, UnaryOp::Bang | UnaryOp::Tilde | UnaryOp::Minus) => {
+                let arg = self.get_rs_arg_from_ts_arg(&u.arg, is_account_struct)?;
+                ts_arg = match u.op {
+                    UnaryOp::Minus => quote! { -#arg },
+                    _ => quote! { !#arg },
+                };
+            }
+            Expr::Bin(b) => {
+                let mut left = self.get_rs_arg_from_ts_arg(&b.left, is_account_struct)?;
+                let mut right = self.get_rs_arg_from_ts_arg(&b.right, is_account_struct)?;
+                if needs_parens(b.op, &b.left) {
+                    left = quote! { (#left) };
+                }
+                if needs_parens(b.op, &b.right) {
+                    right = quote! { (#right) };
+                }
+                let op = rs_binary_op(b.op)?;
+                ts_arg = quote! { #left #op #right };
+            }
+            Expr::Call(c) if cast_call(ts_arg_expr).is_some() => {
+                let (value, to) = cast_call(ts_arg_expr).ok_or(PoseidonError::CallNotFound)?;
+                let value = self.get_rs_arg_from_ts_arg(value, is_account_struct)?;
+                ts_arg = rs_cast(value, self.casts.get(&c.span), to);
+            }
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => {
+                ts_arg = rs_int_literal(lit, None)?;
             }
             _ => {
                 panic!("{:#?} not provided in proper format", ts_arg_expr)
             }
         }
-        if !ix_attribute_token.is_empty() {
-            self.instruction_attributes = Some(ix_attribute_token);
-        }
+        self.add_instruction_attributes(ix_attribute_token);
         Ok(ts_arg)
     }
     pub fn get_seeds(
@@ -151,6 +1662,16 @@ impl ProgramInstruction {
                     #lit_vec
                     });
                 }
+                Expr::Member(_) if self.get_constant(&elem.expr).is_some() => {
+                    let constant = self.get_constant(&elem.expr).unwrap();
+                    let name = constant.ident();
+                    seeds_token.push(match (constant.of_type.as_str(), is_signer_seeds) {
+                        ("Str", _) => quote! { #name.as_bytes() },
+                        ("Pubkey", _) => quote! { #name.as_ref() },
+                        (_, false) => quote! { #name.to_le_bytes().as_ref() },
+                        (_, true) => quote! { &#name.to_le_bytes() },
+                    });
+                }
                 Expr::Member(m) => {
                     let seed_prop = m
                         .prop
@@ -179,13 +1700,30 @@ impl ProgramInstruction {
                                 #seed_obj_ident.key().as_ref()
                             })
                         } else {
+                            let seed_acc = self.get_rs_account(seed_obj);
                             seeds_token.push(quote! {
-                                ctx.accounts.#seed_obj_ident.to_account_info().key.as_ref()
+                                #seed_acc.to_account_info().key.as_ref()
                             });
                         }
+                    } else if let Some(arg_struct) = self.struct_args.get(seed_obj_ident.to_string().as_str()) {
+                        // `Pubkey` and `Str` fields of a struct argument are used as they are
+                        let of_type = arg_struct
+                            .get_field(&seed_prop_ident.to_string())
+                            .map(|f| f.of_type.clone())
+                            .ok_or(anyhow!("{} has no field {}", arg_struct.name, seed_prop))?;
+                        let seed = match of_type.as_str() {
+                            "Pubkey" => quote! { #seed_obj_ident.#seed_prop_ident.as_ref() },
+                            "Str" => quote! { #seed_obj_ident.#seed_prop_ident.as_bytes() },
+                            _ => panic!("Use {}.{}.toBytes() as a seed", seed_obj, seed_prop),
+                        };
+                        if !is_signer_seeds {
+                            ix_attribute_token.push(self.instruction_attribute(&seed_obj_ident));
+                        }
+                        seeds_token.push(seed);
                     } else if is_signer_seeds & (seeds.len() == index + 1) {
+                        let seed_field = self.account_field(&seed_obj_ident, &seed_prop_ident)?;
                         seeds_token.push(quote! {
-                            &[ctx.accounts.#seed_obj_ident.#seed_prop_ident]
+                            &[#seed_field]
                         });
                         is_bump_passed = true;
                     }
@@ -236,7 +1774,7 @@ impl ProgramInstruction {
                         }
 
                         for arg in self.args.iter() {
-                            if arg.name == seed_obj {
+                            if arg.name == seed_obj.to_case(Case::Snake) {
                                 let type_ident = &arg.of_type;
 
                                 ix_attribute_token.push(quote! {
@@ -244,6 +1782,13 @@ impl ProgramInstruction {
                                 })
                             }
                         }
+                    } else if let Some(constant) = self.get_constant(&seed_members.obj) {
+                        let name = constant.ident();
+                        if !is_signer_seeds {
+                            seeds_token.push(quote! { #name.to_le_bytes().as_ref() });
+                        } else {
+                            seeds_token.push(quote! { &#name.to_le_bytes() });
+                        }
                     } else if seed_members.obj.is_member() {
                         if seed_members
                             .prop
@@ -280,12 +1825,18 @@ impl ProgramInstruction {
                             );
 
                             if !is_signer_seeds {
+                                if self.struct_args.contains_key(&seed_obj_ident.to_string()) {
+                                    ix_attribute_token
+                                        .push(self.instruction_attribute(&seed_obj_ident));
+                                }
                                 seeds_token.push(quote! {
                                     #seed_obj_ident.#seed_prop_ident.to_le_bytes().as_ref()
                                 })
                             } else {
+                                let seed_field =
+                                    self.account_field(&seed_obj_ident, &seed_prop_ident)?;
                                 seeds_token.push(quote! {
-                                    &ctx.accounts.#seed_obj_ident.#seed_prop_ident.to_le_bytes()[..]
+                                    &#seed_field.to_le_bytes()[..]
                                 })
                             }
                         }
@@ -294,15 +1845,100 @@ impl ProgramInstruction {
                 _ => {}
             }
         }
-        if !ix_attribute_token.is_empty() {
-            self.instruction_attributes = Some(ix_attribute_token);
-        }
+        self.add_instruction_attributes(ix_attribute_token);
         if is_signer_seeds & !is_bump_passed {
             panic!("Bump not passed in the signer seeds list, add it as the last element of the signer seeds list")
         }
         Ok(seeds_token)
     }
 
+    /// Lowers the seeds of `Pubkey.findProgramAddress([...], programId)` in the instruction body,
+    /// where every seed is borrowed as a byte slice.
+    pub fn get_rs_pda_seeds(
+        &mut self,
+        program_mod: &mut ProgramModule,
+        ix_accounts: &HashMap<String, InstructionAccount>,
+        custom_accounts: &HashMap<String, ProgramAccount>,
+        seeds: &[Option<ExprOrSpread>],
+    ) -> Result<Vec<TokenStream>> {
+        let mut seeds_token: Vec<TokenStream> = vec![];
+        for elem in seeds.iter().flatten() {
+            let seed = match elem.expr.as_ref() {
+                Expr::Lit(Lit::Str(s)) => {
+                    let lit_vec = Literal::byte_string(s.value.as_bytes());
+                    quote! { #lit_vec }
+                }
+                expr @ Expr::Member(_) if self.get_constant(expr).is_some() => {
+                    let constant = self.get_constant(expr).unwrap();
+                    let name = constant.ident();
+                    match constant.of_type.as_str() {
+                        "Str" => quote! { #name.as_bytes() },
+                        "Pubkey" => quote! { #name.as_ref() },
+                        _ => quote! { #name.to_le_bytes().as_ref() },
+                    }
+                }
+                expr @ Expr::Member(m) if m.prop.as_ident().is_some_and(|p| p.sym == "key") => {
+                    let key = self.get_rs_pubkey_in_body(program_mod, ix_accounts, expr)?;
+                    quote! { #key.as_ref() }
+                }
+                expr @ Expr::New(_) => {
+                    let key = self.get_rs_pubkey_in_body(program_mod, ix_accounts, expr)?;
+                    quote! { #key.as_ref() }
+                }
+                Expr::Member(m) => {
+                    let acc = m.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string();
+                    let prop = m.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string();
+                    let of_type = ix_accounts
+                        .get(&acc)
+                        .and_then(|a| custom_accounts.get(&a.type_str))
+                        .and_then(|a| a.fields.iter().find(|f| f.name == prop))
+                        .map(|f| f.of_type.clone())
+                        .ok_or(anyhow!("unsupported seed {}.{}", acc, prop))?;
+                    let obj = Ident::new(&acc.to_case(Case::Snake), Span::call_site());
+                    let prop_ident = Ident::new(&prop.to_case(Case::Snake), Span::call_site());
+                    let field = self.account_field(&obj, &prop_ident)?;
+                    match of_type.as_str() {
+                        "Pubkey" => quote! { #field.as_ref() },
+                        "Str" => quote! { #field.as_bytes() },
+                        _ => return Err(anyhow!("use {}.{}.toBytes() as a seed", acc, prop)),
+                    }
+                }
+                Expr::Call(c) => {
+                    let member = c
+                        .callee
+                        .as_expr()
+                        .and_then(|e| e.as_member())
+                        .ok_or(PoseidonError::MemberNotFound)?;
+                    if member.prop.as_ident().map(|p| p.sym.as_ref()) != Some("toBytes") {
+                        return Err(anyhow!("unsupported seed"));
+                    }
+                    let value = self.get_rs_expr_from_ts_expr(program_mod, ix_accounts, custom_accounts, &member.obj)?;
+                    quote! { #value.to_le_bytes().as_ref() }
+                }
+                Expr::Ident(ident) => {
+                    let name = Ident::new(&ident.sym.as_ref().to_case(Case::Snake), Span::call_site());
+                    let of_type = self
+                        .args
+                        .iter()
+                        .find(|a| name == a.name.as_str())
+                        .map(|a| a.of_type.to_string());
+                    match of_type.as_deref() {
+                        Some("String") => quote! { #name.as_bytes() },
+                        Some("Pubkey") => quote! { #name.as_ref() },
+                        _ if ix_accounts.contains_key(ident.sym.as_ref()) => {
+                            let acc = self.get_rs_account(ident.sym.as_ref());
+                            quote! { #acc.key().as_ref() }
+                        }
+                        _ => return Err(anyhow!("use {}.toBytes() as a seed", ident.sym)),
+                    }
+                }
+                _ => return Err(anyhow!("unsupported seed")),
+            };
+            seeds_token.push(seed);
+        }
+        Ok(seeds_token)
+    }
+
     pub fn from_class_method(
         program_mod: &mut ProgramModule,
         c: &ClassMethod,
@@ -316,28 +1952,56 @@ impl ProgramInstruction {
             .sym
             .to_string();
         let mut ix: ProgramInstruction = ProgramInstruction::new(name);
+        ix.program_name.clone_from(&program_mod.name);
+        ix.constants.clone_from(&program_mod.constants);
+        ix.casts.clone_from(&program_mod.casts);
         // Get accounts and args
         let mut ix_accounts: HashMap<String, InstructionAccount> = HashMap::new();
         let mut ix_arguments: Vec<InstructionArgument> = vec![];
         let mut ix_body: Vec<TokenStream> = vec![];
-        c.function.params.iter().for_each(|p| {
+        // a `tokenProgram: TokenInterface` parameter switches the token accounts of the instruction
+        // to `token_interface`, so they accept both Token and Token-2022 mints
+        ix.uses_token_interface = c.function.params.iter().any(|p| {
+            p.pat
+                .as_ident()
+                .and_then(|b| b.type_ann.clone())
+                .and_then(|t| extract_ts_type(t).ok())
+                .is_some_and(|(of_type, _, _)| of_type == "TokenInterface")
+        });
+        let token_mod = match ix.uses_token_interface {
+            true => "token_interface",
+            false => "token",
+        };
+        let token_account_type = match ix.uses_token_interface {
+            true => quote! { InterfaceAccount<'info, TokenAccount> },
+            false => quote! { Account<'info, TokenAccount> },
+        };
+        let mint_type = match ix.uses_token_interface {
+            true => quote! { InterfaceAccount<'info, Mint> },
+            false => quote! { Account<'info, Mint> },
+        };
+        c.function.params.iter().map(|p| {
             let BindingIdent { id, type_ann } = p.pat.clone().expect_ident();
             let name = id.sym.to_string();
             let snaked_name = id.sym.to_string().to_case(Case::Snake);
             let binding = type_ann.expect("Invalid type annotation");
             let (of_type, _len, optional) = extract_ts_type(binding)
                 .unwrap_or_else(|_| panic!("Keyword type is not supported"));
+            // `name?: Type` marks the parameter itself as optional
+            let optional = optional || id.optional;
 
             if STANDARD_TYPES.contains(&of_type.as_str())
                 | STANDARD_ARRAY_TYPES.contains(&of_type.as_str())
             {
                 let rs_type = rs_type_from_str(&of_type)
                     .unwrap_or_else(|_| panic!("Invalid type: {}", of_type));
+                let of_type = match optional {
+                    true => quote!(Option<#rs_type>),
+                    false => quote!(#rs_type),
+                };
                 ix_arguments.push(InstructionArgument {
                     name: snaked_name,
-                    of_type: quote!(
-                        #rs_type,
-                    ),
+                    of_type,
                     optional,
                 })
             } else if STANDARD_ACCOUNT_TYPES.contains(&of_type.as_str()) {
@@ -383,57 +2047,93 @@ impl ProgramInstruction {
                         name.clone(),
                         InstructionAccount::new(
                             snaked_name.clone(),
-                            quote! { Account<'info, TokenAccount> },
+                            token_account_type.clone(),
                             of_type,
                             optional,
                         ),
                     );
                     ix.uses_associated_token_program = true;
                     ix.uses_token_program = true;
+                    let cur_ix_acc = ix_accounts.get_mut(&name.clone()).unwrap();
+                    cur_ix_acc.token_interface = ix.uses_token_interface;
 
                     program_mod.add_import("anchor_spl", "associated_token", "AssociatedToken");
-                    program_mod.add_import("anchor_spl", "token", "TokenAccount");
-                    program_mod.add_import("anchor_spl", "token", "Token");
+                    program_mod.add_import("anchor_spl", token_mod, "TokenAccount");
+                    if !ix.uses_token_interface {
+                        program_mod.add_import("anchor_spl", "token", "Token");
+                    }
                 } else if of_type == "Mint" {
                     ix_accounts.insert(
                         name.clone(),
                         InstructionAccount::new(
                             snaked_name.clone(),
-                            quote! { Account<'info, Mint> },
+                            mint_type.clone(),
                             of_type,
                             optional,
                         ),
                     );
-                    program_mod.add_import("anchor_spl", "token", "Mint");
+                    program_mod.add_import("anchor_spl", token_mod, "Mint");
                     let cur_ix_acc = ix_accounts.get_mut(&name.clone()).unwrap();
                     cur_ix_acc.is_mut = true;
+                    cur_ix_acc.token_interface = ix.uses_token_interface;
                     ix.uses_token_program = true;
-                    program_mod.add_import("anchor_spl", "token", "Token");
+                    if !ix.uses_token_interface {
+                        program_mod.add_import("anchor_spl", "token", "Token");
+                    }
                 } else if of_type == "TokenAccount" {
                     ix_accounts.insert(
                         name.clone(),
                         InstructionAccount::new(
                             snaked_name.clone(),
-                            quote! { Account<'info, TokenAccount> },
+                            token_account_type.clone(),
+                            of_type,
+                            optional,
+                        ),
+                    );
+                    ix.uses_token_program = true;
+                    let cur_ix_acc = ix_accounts.get_mut(&name.clone()).unwrap();
+                    cur_ix_acc.token_interface = ix.uses_token_interface;
+                    program_mod.add_import("anchor_spl", token_mod, "TokenAccount");
+                    if !ix.uses_token_interface {
+                        program_mod.add_import("anchor_spl", "token", "Token");
+                    }
+                } else if of_type == "MetadataAccount" {
+                    ix_accounts.insert(
+                        name.clone(),
+                        InstructionAccount::new(
+                            snaked_name.clone(),
+                            quote! { Account<'info, MetadataAccount> },
                             of_type,
                             optional,
                         ),
                     );
+                    program_mod.add_import("anchor_spl", "metadata", "MetadataAccount");
+                } else if of_type == "TokenInterface" {
+                    if snaked_name != "token_program" {
+                        return Err(anyhow!("Name the TokenInterface parameter tokenProgram"));
+                    }
                     ix.uses_token_program = true;
-                    program_mod.add_import("anchor_spl", "token", "TokenAccount");
-                    program_mod.add_import("anchor_spl", "token", "Token");
+                    program_mod.add_import("anchor_spl", "token_interface", "TokenInterface");
                 }
             } else if custom_accounts.contains_key(&of_type) {
                 let ty = Ident::new(&of_type, proc_macro2::Span::call_site());
+                let is_zero_copy = custom_accounts[&of_type].is_zero_copy;
+                let acc_type = match is_zero_copy {
+                    true => quote! { AccountLoader<'info, #ty> },
+                    false => quote! { Account<'info, #ty> },
+                };
                 ix_accounts.insert(
                     name.clone(),
                     InstructionAccount::new(
                         snaked_name.clone(),
-                        quote! { Account<'info, #ty> },
+                        acc_type,
                         of_type.clone(),
                         optional,
                     ),
                 );
+                if is_zero_copy {
+                    ix.zero_copy_accounts.push(snaked_name.clone());
+                }
                 ix.uses_system_program = true;
                 let cur_ix_acc = ix_accounts.get_mut(&name.clone()).unwrap();
                 cur_ix_acc.space = Some(
@@ -443,188 +2143,325 @@ impl ProgramInstruction {
                         .space,
                 );
                 cur_ix_acc.is_custom = true;
+                cur_ix_acc.init_space = program_mod.init_space;
+                cur_ix_acc.is_zero_copy = is_zero_copy;
+            } else if let Some(arg_struct) =
+                program_mod.structs.iter().find(|s| s.name == of_type)
+            {
+                let ty = Ident::new(&of_type, proc_macro2::Span::call_site());
+                ix.struct_args.insert(snaked_name.clone(), arg_struct.clone());
+                ix_arguments.push(InstructionArgument {
+                    name: snaked_name,
+                    of_type: quote!(#ty),
+                    optional: false,
+                })
             } else {
                 panic!("Invalid variable or account type: {}", of_type);
             }
-        });
+            Ok(())
+        }).collect::<Result<Vec<()>>>()?;
         ix.args = ix_arguments;
 
-        let _ = c.clone()
-            .function
-            .body
-            .ok_or(anyhow!("block statement none"))
-            ?.stmts
-            .iter()
-            .map(|s| {
+        let mut body_items: Vec<BodyItem> = vec![];
+        flatten_blocks(&c.function.body.as_ref().ok_or(anyhow!("block statement none"))?.stmts, &mut body_items, false)?;
+        let mut block_stack: Vec<OpenBlock> = vec![];
+        // the zero-copy accounts each statement of ix_body uses
+        let mut body_guards: Vec<Vec<String>> = vec![];
+        let _ = body_items
+            .into_iter()
+            .map(|item| {
+                // statements pushed since the last item belong to it, and use the guards it
+                // recorded apart from those held by an enclosing block's header
+                let held: Vec<String> = block_stack.iter().flat_map(|b| b.guards.clone()).collect();
+                let uses: Vec<String> = std::mem::take(&mut ix.zero_copy_uses).into_iter().filter(|acc| !held.contains(acc)).collect();
+                body_guards.resize(ix_body.len(), uses);
+                let s = match item {
+                    BodyItem::Stmt(s) => s,
+                    BodyItem::BlockStart(block_stmt) => {
+                        let (header, bound_account) = match &block_stmt {
+                            Stmt::If(if_stmt) => ix.get_rs_if_header(program_mod, &ix_accounts, custom_accounts, &if_stmt.test)?,
+                            loop_stmt => {
+                                let header = ix.get_rs_loop_header(loop_stmt)?;
+                                (quote!{ for #header }, None)
+                            }
+                        };
+                        let guards = std::mem::take(&mut ix.zero_copy_uses).into_iter().filter(|acc| !held.contains(acc)).collect();
+                        // the body before `else` uses the account through the variable binding it
+                        if let Some(bound_account) = &bound_account {
+                            ix.bound_accounts.push(bound_account.clone());
+                        }
+                        block_stack.push(OpenBlock {
+                            outer_body: std::mem::take(&mut ix_body),
+                            outer_guards: std::mem::take(&mut body_guards),
+                            header,
+                            guards,
+                            bound_account,
+                            then_body: None,
+                        });
+                        return Ok(());
+                    }
+                    BodyItem::Else => {
+                        let block = block_stack.last_mut().ok_or(anyhow!("unbalanced block"))?;
+                        if block.bound_account.take().is_some() {
+                            ix.bound_accounts.pop();
+                        }
+                        block.then_body = Some((std::mem::take(&mut ix_body), std::mem::take(&mut body_guards)));
+                        return Ok(());
+                    }
+                    BodyItem::BlockEnd => {
+                        let OpenBlock { outer_body, outer_guards, header, guards, bound_account, then_body } = block_stack.pop().ok_or(anyhow!("unbalanced block"))?;
+                        let block_body = std::mem::replace(&mut ix_body, outer_body);
+                        let block_guards = std::mem::replace(&mut body_guards, outer_guards);
+                        let block_body = scope_zero_copy_guards(&ix_accounts, block_body, block_guards)?;
+                        let (then_body, else_body) = match then_body {
+                            Some((then_body, then_guards)) => (scope_zero_copy_guards(&ix_accounts, then_body, then_guards)?, Some(block_body)),
+                            None => (block_body, None),
+                        };
+                        if bound_account.is_some() {
+                            ix.bound_accounts.pop();
+                        }
+                        let block = match else_body {
+                            Some(else_body) => quote!{
+                                #header {
+                                    #(#then_body)*
+                                } else {
+                                    #(#else_body)*
+                                }
+                            },
+                            None => quote!{
+                                #header {
+                                    #(#then_body)*
+                                }
+                            },
+                        };
+                        // the guards of the header are held around the whole block
+                        ix_body.push(block);
+                        body_guards.push(guards);
+                        return Ok(());
+                    }
+                };
                 match s.clone() {
                     Stmt::Expr(e) => {
                         let s = e.expr;
+                        if let Some(collection_stmt) = ix.get_rs_collection_stmt(program_mod, &mut ix_accounts, custom_accounts, &s)? {
+                            ix_body.push(collection_stmt);
+                            return Ok(());
+                        }
+                        if let Some(lamports_stmt) = ix.get_rs_lamports_stmt(program_mod, &mut ix_accounts, custom_accounts, &s)? {
+                            ix_body.push(lamports_stmt);
+                            return Ok(());
+                        }
                         match *s {
+                            Expr::Call(c) if c.callee.as_expr().and_then(|e| e.as_ident()).is_some_and(|i| i.sym == "invoke" || i.sym == "invokeSigned") => {
+                                let invoke_fn = c.callee.as_expr().and_then(|e| e.as_ident()).ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                let expected_args = if invoke_fn == "invoke" { 2 } else { 3 };
+                                if c.args.len() != expected_args {
+                                    return Err(anyhow!("{} expects {} arguments", invoke_fn, expected_args));
+                                }
+                                let instruction = match c.args[0].expr.as_ref() {
+                                    Expr::Ident(i) => {
+                                        let instruction_ident = Ident::new(&i.sym.as_ref().to_case(Case::Snake), proc_macro2::Span::call_site());
+                                        quote!{ &#instruction_ident }
+                                    }
+                                    instruction_expr => {
+                                        let instruction = ix.get_rs_instruction_from_ts_expr(program_mod, &mut ix_accounts, instruction_expr)?;
+                                        quote!{ &#instruction }
+                                    }
+                                };
+                                let mut account_infos: Vec<TokenStream> = vec![];
+                                for acc in c.args[1].expr.as_array().ok_or(anyhow!("expected the accounts of {} as an array", invoke_fn))?.elems.iter().flatten() {
+                                    account_infos.push(ix.get_rs_account_info_from_ts_expr(&acc.expr)?);
+                                }
+                                if invoke_fn == "invoke" {
+                                    ix_body.push(quote!{
+                                        anchor_lang::solana_program::program::invoke(
+                                            #instruction,
+                                            &[#(#account_infos),*],
+                                        )?;
+                                    });
+                                } else {
+                                    let seeds = c.args[2].expr.as_array().ok_or(anyhow!("expected the seeds of invokeSigned as an array"))?;
+                                    let seed_tokens_vec = ix.get_seeds(&seeds.elems, true)?;
+                                    ix_body.push(quote!{
+                                        let signer_seeds: &[&[&[u8]]; 1] = &[&[#(#seed_tokens_vec),*]];
+                                        anchor_lang::solana_program::program::invoke_signed(
+                                            #instruction,
+                                            &[#(#account_infos),*],
+                                            signer_seeds,
+                                        )?;
+                                    });
+                                }
+                            }
                             Expr::Call(c) => {
-                                let parent_call = c.callee.as_expr().ok_or(PoseidonError::ExprNotFound)?.as_member().ok_or(PoseidonError::MemberNotFound)?;
-                                let members: &MemberExpr;
+                                // flatten `acc.derive(...).init(...).has(...)` into the root ident and its method calls, in call order
+                                let mut chain: Vec<(&str, &CallExpr)> = vec![];
                                 let mut obj = "";
-                                let mut prop = "";
-                                let mut derive_args: &Vec<ExprOrSpread> = &vec![];
-                                if parent_call.obj.is_call() {
-                                    members = parent_call
-                                        .obj
-                                        .as_call()
-                                        .ok_or(PoseidonError::CallNotFound)
-                                        ?.callee
-                                        .as_expr()
-                                        .ok_or(PoseidonError::ExprNotFound)
-                                        ?.as_member()
-                                        .ok_or(PoseidonError::MemberNotFound)?;
-                                    if members.obj.is_ident(){
-                                        obj = members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        prop = members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        if prop == "derive" {
-                                            derive_args = &parent_call.obj.as_call().ok_or(PoseidonError::CallNotFound)?.args;
-                                        }
-                                    } else if members.obj.is_call() {
-                                        let sub_members = members.obj.as_call().ok_or(PoseidonError::CallNotFound)?.callee.as_expr().ok_or(PoseidonError::ExprNotFound)?.as_member().ok_or(PoseidonError::MemberNotFound)?;
-                                        obj = sub_members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        prop = sub_members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        if prop == "derive" {
-                                            derive_args = &members.obj.as_call().ok_or(PoseidonError::CallNotFound)?.args;
+                                let mut cur_call = &c;
+                                loop {
+                                    let members = cur_call.callee.as_expr().ok_or(PoseidonError::ExprNotFound)?.as_member().ok_or(PoseidonError::MemberNotFound)?;
+                                    chain.push((members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref(), cur_call));
+                                    match members.obj.as_ref() {
+                                        Expr::Call(inner_call) => cur_call = inner_call,
+                                        Expr::Ident(obj_ident) => {
+                                            obj = obj_ident.sym.as_ref();
+                                            break;
                                         }
-                                    }
-                                } else if parent_call.obj.is_ident() {
-                                    obj = parent_call.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                    prop = parent_call.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                    if prop.contains("derive") {
-                                        derive_args = &c.args;
+                                        _ => break,
                                     }
                                 }
+                                chain.reverse();
+                                let prop = chain[0].0;
                                 if let Some(cur_ix_acc) = ix_accounts.get_mut(obj) {
-                                    if prop.contains("derive") {
-                                        let chaincall1prop = c
-                                            .callee
-                                            .as_expr()
-                                            .ok_or(PoseidonError::ExprNotFound)
-                                            ?.as_member()
-                                            .ok_or(PoseidonError::MemberNotFound)
-                                            ?.prop
-                                            .as_ident()
-                                            .ok_or(PoseidonError::IdentNotFound)
-                                            ?.sym
-                                            .as_ref();
-                                        let mut chaincall2prop = "";
-                                        if c.clone().callee.expect_expr().expect_member().obj.is_call(){
-                                            chaincall2prop = c
-                                                                .callee
-                                                                .as_expr()
-                                                                .ok_or(PoseidonError::ExprNotFound)
-                                                                ?.as_member()
-                                                                .ok_or(PoseidonError::MemberNotFound)
-                                                                ?.obj
-                                                                .as_call()
-                                                                .ok_or(PoseidonError::CallNotFound)
-                                                                ?.callee
-                                                                .as_expr()
-                                                                .ok_or(PoseidonError::ExprNotFound)
-                                                                ?.as_member()
-                                                                .ok_or(PoseidonError::MemberNotFound)
-                                                                ?.prop
-                                                                .as_ident()
-                                                                .ok_or(PoseidonError::IdentNotFound)
-                                                                ?.sym
-                                                                .as_ref();
-                                        }
-                                        if cur_ix_acc.type_str == "AssociatedTokenAccount" {
-                                            let mint = derive_args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let ata_auth = derive_args[1].expr.as_member().ok_or(PoseidonError::MemberNotFound)?.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            cur_ix_acc.ta = Some(
-                                                Ta {
-                                                    mint: mint.to_case(Case::Snake),
-                                                    authority: ata_auth.to_case(Case::Snake),
-                                                    is_ata: true,
-                                                }
-                                            );
-                                            cur_ix_acc.is_mut = true;
-                                        } else if cur_ix_acc.type_str == "TokenAccount" {
-                                            let mint = derive_args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let ta_auth = derive_args[2].expr.as_member().ok_or(PoseidonError::MemberNotFound)?.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            cur_ix_acc.ta = Some(
-                                                Ta {
-                                                    mint: mint.to_case(Case::Snake),
-                                                    authority: ta_auth.to_case(Case::Snake),
-                                                    is_ata: false,
+                                    let mut derive_args: &Vec<ExprOrSpread> = &vec![];
+                                    for (method, method_call) in chain.iter() {
+                                        let args = &method_call.args;
+                                        match *method {
+                                            "derive" | "deriveWithBump" => {
+                                                derive_args = args;
+                                                if cur_ix_acc.type_str == "AssociatedTokenAccount" {
+                                                    let mint = derive_args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                                    let ata_auth = derive_args[1].expr.as_member().ok_or(PoseidonError::MemberNotFound)?.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                                    cur_ix_acc.ta = Some(
+                                                        Ta {
+                                                            mint: mint.to_case(Case::Snake),
+                                                            authority: ata_auth.to_case(Case::Snake),
+                                                            is_ata: true,
+                                                            authority_error: None,
+                                                        }
+                                                    );
+                                                    cur_ix_acc.is_mut = true;
+                                                } else if cur_ix_acc.type_str == "TokenAccount" {
+                                                    let mint = derive_args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                                    let ta_auth = derive_args[2].expr.as_member().ok_or(PoseidonError::MemberNotFound)?.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                                    // derive(seeds, mint, authority, error?) or deriveWithBump(seeds, mint, authority, error?, bump)
+                                                    let authority_error_arg = match *method {
+                                                        "derive" => derive_args.get(3),
+                                                        _ if derive_args.len() == 5 => derive_args.get(3),
+                                                        _ => None,
+                                                    };
+                                                    let authority_error = authority_error_arg.map(|e| ix.get_rs_error_from_ts_expr(&e.expr)).transpose()?;
+                                                    cur_ix_acc.ta = Some(
+                                                        Ta {
+                                                            mint: mint.to_case(Case::Snake),
+                                                            authority: ta_auth.to_case(Case::Snake),
+                                                            is_ata: false,
+                                                            authority_error,
+                                                        }
+                                                    );
+                                                    cur_ix_acc.is_mut = true;
                                                 }
-                                            );
-                                            cur_ix_acc.is_mut = true;
-                                        }
-                                        if (cur_ix_acc.type_str != "AssociatedTokenAccount") & (cur_ix_acc.type_str != "Mint") {
-
-                                            let seeds = &derive_args[0].expr.as_array().ok_or(anyhow!("expected an array"))?.elems;
-                                            let seeds_token = ix.get_seeds(seeds, false)?;
-                                            cur_ix_acc.bump = Some(quote!{
-                                                bump
-                                            });
-                                            if !seeds_token.is_empty() {
-                                                cur_ix_acc.seeds = Some(seeds_token);
-                                            }
+                                                if (cur_ix_acc.type_str != "AssociatedTokenAccount") & (cur_ix_acc.type_str != "Mint") {
 
-                                        }
-                                        if prop == "deriveWithBump" {
-                                            let bump_members = c.args.last().ok_or(anyhow!("no last element in vector"))?.expr.as_member().ok_or(PoseidonError::MemberNotFound)?;
-                                            let bump_prop  = Ident::new(
-                                                &bump_members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake),
-                                                Span::call_site(),
-                                            );
-                                            let bump_obj = Ident::new(
-                                                &bump_members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake),
-                                                Span::call_site(),
-                                            );
-                                            cur_ix_acc.bump = Some(quote!{
-                                                bump = #bump_obj.#bump_prop
-                                            })
-                                        }
+                                                    let seeds = &derive_args[0].expr.as_array().ok_or(anyhow!("expected an array"))?.elems;
+                                                    let seeds_token = ix.get_seeds(seeds, false)?;
+                                                    cur_ix_acc.bump = Some(quote!{
+                                                        bump
+                                                    });
+                                                    if !seeds_token.is_empty() {
+                                                        cur_ix_acc.seeds = Some(seeds_token);
+                                                    }
 
-                                        if chaincall1prop == "init" {
-                                            ix.uses_system_program = true;
-                                            cur_ix_acc.is_init = true;
-                                            cur_ix_acc.payer = Some(c.args.get(0).ok_or(anyhow!("Pass the payer account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
-                                        }
-                                        else if chaincall1prop == "initIfNeeded" {
-                                            ix.uses_system_program = true;
-                                            cur_ix_acc.is_initifneeded = true;
-                                            cur_ix_acc.payer = Some(c.args.get(0).ok_or(anyhow!("Pass the payer account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
-                                        }
-                                        if chaincall1prop == "close" {
-                                            cur_ix_acc.close = Some(c.args.get(0).ok_or(anyhow!("Pass the destination account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
-                                            cur_ix_acc.is_mut = true;
-                                        }
-                                        if chaincall2prop == "has" {
-                                            let elems = &c.callee.as_expr().ok_or(PoseidonError::ExprNotFound)?.as_member().ok_or(PoseidonError::MemberNotFound)?.obj.as_call().ok_or(PoseidonError::CallNotFound)?.args.get(0).ok_or(anyhow!("Pass the accounts array argument for has method"))?.expr.as_array().ok_or(anyhow!("expected a array"))?.elems;
-                                            let mut has_one:Vec<String> = vec![];
-                                            for elem in elems.into_iter().flatten() {
-                                                    has_one.push(elem.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string().to_case(Case::Snake));
+                                                }
+                                                if *method == "deriveWithBump" {
+                                                    let bump_members = args.last().ok_or(anyhow!("no last element in vector"))?.expr.as_member().ok_or(PoseidonError::MemberNotFound)?;
+                                                    let bump_prop  = Ident::new(
+                                                        &bump_members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake),
+                                                        Span::call_site(),
+                                                    );
+                                                    let bump_obj = Ident::new(
+                                                        &bump_members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake),
+                                                        Span::call_site(),
+                                                    );
+                                                    cur_ix_acc.bump = Some(quote!{
+                                                        bump = #bump_obj.#bump_prop
+                                                    })
+                                                }
                                             }
-                                            cur_ix_acc.has_one = has_one;
-                                        }
-                                    } else if prop == "init" {
-                                        ix.uses_system_program = true;
-                                        cur_ix_acc.is_init = true;
-                                        cur_ix_acc.payer = Some(c.args.get(0).ok_or(anyhow!("Pass the payer account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
-                                    } else if prop == "initIfNeeded" {
-                                        ix.uses_system_program = true;
-                                        cur_ix_acc.is_initifneeded = true;
-                                        cur_ix_acc.payer = Some(c.args.get(0).ok_or(anyhow!("Pass the payer account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
-                                    } else if prop == "close" {
-                                        cur_ix_acc.close = Some(c.args.get(0).ok_or(anyhow!("Pass the destination account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
-                                        cur_ix_acc.is_mut = true;
-                                    } else if prop == "has" {
-                                        let elems = &c.args.get(0).ok_or(anyhow!("Pass the accounts array argument for has method"))?.expr.as_array().ok_or(anyhow!("expected a array"))?.elems;
-                                        let mut has_one:Vec<String> = vec![];
-                                        for elem in elems.into_iter().flatten() {
-                                                has_one.push(elem.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string().to_case(Case::Snake));
+                                            "init" => {
+                                                ix.uses_system_program = true;
+                                                cur_ix_acc.is_init = true;
+                                                cur_ix_acc.payer = Some(args.first().ok_or(anyhow!("Pass the payer account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
+                                            }
+                                            "initIfNeeded" => {
+                                                ix.uses_system_program = true;
+                                                cur_ix_acc.is_initifneeded = true;
+                                                cur_ix_acc.payer = Some(args.first().ok_or(anyhow!("Pass the payer account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
+                                            }
+                                            "close" => {
+                                                cur_ix_acc.close = Some(args.first().ok_or(anyhow!("Pass the destination account argument for init"))?.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref().to_case(Case::Snake));
+                                                cur_ix_acc.close_error = args.get(1).map(|e| ix.get_rs_error_from_ts_expr(&e.expr)).transpose()?;
+                                                cur_ix_acc.is_mut = true;
+                                            }
+                                            "realloc" => {
+                                                ix.set_realloc(cur_ix_acc, args)?;
+                                            }
+                                            "has" => {
+                                                let elems = &args.first().ok_or(anyhow!("Pass the accounts array argument for has method"))?.expr.as_array().ok_or(anyhow!("expected a array"))?.elems;
+                                                let has_error = args.get(1).map(|e| ix.get_rs_error_from_ts_expr(&e.expr)).transpose()?;
+                                                for elem in elems.iter().flatten() {
+                                                        cur_ix_acc.has_one.push((elem.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.to_string().to_case(Case::Snake), has_error.clone()));
+                                                }
+                                            }
+                                            "constraint" => {
+                                                let constraint_expr = &args.first().ok_or(anyhow!("Pass the boolean expression argument for constraint method"))?.expr;
+                                                let constraint = ix.get_rs_constraint_from_ts_expr(program_mod, constraint_expr)?;
+                                                match args.get(1) {
+                                                    Some(error_arg) => {
+                                                        let error = ix.get_rs_error_from_ts_expr(&error_arg.expr)?;
+                                                        cur_ix_acc.constraints.push(quote!{ #constraint @ #error });
+                                                    }
+                                                    None => cur_ix_acc.constraints.push(constraint),
+                                                }
+                                            }
+                                            "metadataPointer" | "groupPointer" | "groupMemberPointer" | "closeAuthority" | "permanentDelegate" | "transferHook" => {
+                                                if cur_ix_acc.type_str != "Mint" || !ix.uses_token_interface {
+                                                    return Err(anyhow!("{} is a Token-2022 mint extension, use it on a Mint in an instruction with a `tokenProgram: TokenInterface` parameter", method));
+                                                }
+                                                let mut ext_accounts: Vec<Ident> = vec![];
+                                                for arg in args.iter() {
+                                                    ext_accounts.push(ix.get_rs_account_from_ts_expr(&arg.expr)?);
+                                                }
+                                                let (extension, keys) = match *method {
+                                                    "metadataPointer" => ("metadata_pointer", vec!["authority", "metadata_address"]),
+                                                    "groupPointer" => ("group_pointer", vec!["authority", "group_address"]),
+                                                    "groupMemberPointer" => ("group_member_pointer", vec!["authority", "member_address"]),
+                                                    "closeAuthority" => ("close_authority", vec!["authority"]),
+                                                    "permanentDelegate" => ("permanent_delegate", vec!["delegate"]),
+                                                    _ => ("transfer_hook", vec!["authority", "program_id"]),
+                                                };
+                                                if ext_accounts.len() != keys.len() {
+                                                    return Err(anyhow!("{} expects {} account arguments: {}", method, keys.len(), keys.join(", ")));
+                                                }
+                                                let extension = Ident::new(extension, Span::call_site());
+                                                for (key, acc) in keys.iter().zip(ext_accounts.iter()) {
+                                                    let key = Ident::new(key, Span::call_site());
+                                                    cur_ix_acc.mint_extensions.push(quote!{ extensions::#extension::#key = #acc });
+                                                }
+                                            }
+                                            // anchor 0.30 has no constraints for these extensions
+                                            "transferFee" | "interestBearing" | "nonTransferable" | "defaultAccountState" => {
+                                                return Err(anyhow!("The {} mint extension is not supported, since Anchor has no constraint to initialize it", method));
+                                            }
+                                            "mut" => {
+                                                cur_ix_acc.is_mut = true;
+                                            }
+                                            "signer" => {
+                                                cur_ix_acc.is_signer = true;
+                                            }
+                                            "address" => {
+                                                let address_expr = &args.first().ok_or(anyhow!("Pass the pubkey argument for address method"))?.expr;
+                                                cur_ix_acc.address = Some(ix.get_rs_pubkey_from_ts_expr(program_mod, address_expr)?);
+                                                cur_ix_acc.address_error = args.get(1).map(|e| ix.get_rs_error_from_ts_expr(&e.expr)).transpose()?;
+                                            }
+                                            "owner" => {
+                                                let owner_expr = &args.first().ok_or(anyhow!("Pass the program id argument for owner method"))?.expr;
+                                                cur_ix_acc.owner = Some(ix.get_rs_pubkey_from_ts_expr(program_mod, owner_expr)?);
+                                            }
+                                            _ => {}
                                         }
-                                        cur_ix_acc.has_one = has_one;
                                     }
-                                    if cur_ix_acc.type_str == "Mint" {
-                                        match *(derive_args.get(0).ok_or(anyhow!("Seed array not passed while deriving the mint. should be either a array or null type"))?.expr.clone()) {
+                                    if cur_ix_acc.type_str == "Mint" && !derive_args.is_empty() {
+                                        match *(derive_args[0].expr.clone()) {
                                             Expr::Lit(Lit::Null(_)) => {},
                                             Expr::Array(seed_array) => {
                                                 let seeds = &seed_array.elems;
@@ -643,15 +2480,15 @@ impl ProgramInstruction {
                                         if derive_args.len() > 2 {
                                             let mint_authority_expr = &derive_args.get(1).ok_or(anyhow!("Mint authority not passed while deriving the mint"))?.expr;
 
-                                            let mint_authority_token = ix.get_rs_arg_from_ts_arg(&mint_authority_expr, true)?;
+                                            let mint_authority_token = ix.get_rs_arg_from_ts_arg(mint_authority_expr, true)?;
 
                                             let decimal_expr = &derive_args.get(2).ok_or(anyhow!("Decimals not passed while deriving the mint"))?.expr;
-                                            let decimals_token = ix.get_rs_arg_from_ts_arg(&decimal_expr, true)?;
+                                            let decimals_token = ix.get_rs_arg_from_ts_arg(decimal_expr, true)?;
                                             let mut freeze_authority_token: Option<TokenStream> = None;
 
                                             if derive_args.len() == 4 {
                                                 let freeze_auth_expr = &derive_args.get(3).ok_or(anyhow!("Decimals not passed while deriving the mint"))?.expr;
-                                            freeze_authority_token = ix.get_rs_arg_from_ts_arg(&freeze_auth_expr, true).ok();
+                                            freeze_authority_token = ix.get_rs_arg_from_ts_arg(freeze_auth_expr, true).ok();
                                             }
 
                                             let mint = Mint {
@@ -666,13 +2503,13 @@ impl ProgramInstruction {
                                     }
                                 }
                                 if obj == "SystemProgram" {
+                                    ix.uses_system_program = true;
                                     if prop == "transfer" {
                                         program_mod.add_import("anchor_lang", "system_program", "Transfer");
                                         program_mod.add_import("anchor_lang", "system_program", "transfer");
                                         let from_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        let to_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        let from_acc_ident = Ident::new(&from_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                        let to_acc_ident = Ident::new(&to_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                        let from_acc_ident = ix.get_rs_account(from_acc);
+                                        let to_acc_info = ix.get_rs_account_info_from_ts_expr(&c.args[1].expr)?;
                                         let amount_expr = &c.args[2].expr;
                                         let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
                                         if let Some(cur_ix_acc) = ix_accounts.get(from_acc){
@@ -687,8 +2524,8 @@ impl ProgramInstruction {
 
                                                 ix_body.push(quote!{
                                                     let transfer_accounts = Transfer {
-                                                        from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                        to: ctx.accounts.#to_acc_ident.to_account_info()
+                                                        from: #from_acc_ident.to_account_info(),
+                                                        to: #to_acc_info
                                                     };
 
                                                     let signer_seeds: &[&[&[u8]]; 1] = #signer_var_token_stream
@@ -698,13 +2535,13 @@ impl ProgramInstruction {
                                                         transfer_accounts,
                                                         signer_seeds
                                                     );
-                                                    transfer(cpi_ctx, amount)?;
+                                                    transfer(cpi_ctx, #amount)?;
                                                 });
                                             } else {
                                                 ix_body.push(quote!{
                                                     let transfer_accounts = Transfer {
-                                                        from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                        to: ctx.accounts.#to_acc_ident.to_account_info()
+                                                        from: #from_acc_ident.to_account_info(),
+                                                        to: #to_acc_info
                                                     };
                                                     let cpi_ctx = CpiContext::new(
                                                         ctx.accounts.system_program.to_account_info(),
@@ -715,20 +2552,149 @@ impl ProgramInstruction {
                                             }
                                         }
 
+                                    } else {
+                                        // accounts first, then the values, then optional signer seeds for a PDA
+                                        let (cpi_args, signer_seeds) = match c.args.last().map(|a| a.expr.as_ref()) {
+                                            Some(Expr::Array(seeds)) => (&c.args[..c.args.len() - 1], Some(ix.get_seeds(&seeds.elems, true)?)),
+                                            _ => (&c.args[..], None),
+                                        };
+                                        let (account_count, value_count, cpi_fn, cpi_struct) = match prop {
+                                            "createAccount" => (2, 3, "create_account", "CreateAccount"),
+                                            "createAccountWithSeed" => (3, 4, "create_account_with_seed", "CreateAccountWithSeed"),
+                                            "allocate" => (1, 1, "allocate", "Allocate"),
+                                            "assign" => (1, 1, "assign", "Assign"),
+                                            "transferWithSeed" => (3, 3, "transfer_with_seed", "TransferWithSeed"),
+                                            _ => return Err(anyhow!("SystemProgram.{} is not supported", prop)),
+                                        };
+                                        if cpi_args.len() != account_count + value_count {
+                                            return Err(anyhow!("SystemProgram.{} expects {} arguments besides the signer seeds", prop, account_count + value_count));
+                                        }
+                                        program_mod.add_import("anchor_lang", "system_program", cpi_fn);
+                                        program_mod.add_import("anchor_lang", "system_program", cpi_struct);
+                                        let cpi_fn = Ident::new(cpi_fn, proc_macro2::Span::call_site());
+                                        let cpi_struct = Ident::new(cpi_struct, proc_macro2::Span::call_site());
+
+                                        let mut accs: Vec<TokenStream> = vec![];
+                                        for arg in cpi_args[..account_count].iter() {
+                                            if let Some(cpi_acc) = arg.expr.as_ident().and_then(|acc| ix_accounts.get_mut(acc.sym.as_ref())) {
+                                                cpi_acc.is_mut = true;
+                                            }
+                                            accs.push(ix.get_rs_account_info_from_ts_expr(&arg.expr)?);
+                                        }
+                                        let values = &cpi_args[account_count..];
+                                        let mut value_tokens: Vec<TokenStream> = vec![];
+                                        for (index, value) in values.iter().enumerate() {
+                                            let is_owner = matches!((prop, index), ("createAccount", 2) | ("createAccountWithSeed", 3) | ("assign", 0) | ("transferWithSeed", 1));
+                                            let is_seed = index == 0 && (prop == "createAccountWithSeed" || prop == "transferWithSeed");
+                                            let value_token = match value.expr.as_ref() {
+                                                Expr::Lit(Lit::Str(s)) if is_seed => {
+                                                    let seed_literal = Literal::string(&s.value);
+                                                    match prop {
+                                                        "transferWithSeed" => quote!{ #seed_literal.to_string() },
+                                                        _ => quote!{ #seed_literal },
+                                                    }
+                                                }
+                                                seed_expr if is_seed => {
+                                                    let seed = ix.get_rs_arg_from_ts_arg(seed_expr, false)?;
+                                                    match prop {
+                                                        "transferWithSeed" => quote!{ #seed.to_string() },
+                                                        _ => quote!{ &#seed },
+                                                    }
+                                                }
+                                                owner_expr if is_owner => {
+                                                    let owner = ix.get_rs_pubkey_in_body(program_mod, &ix_accounts, owner_expr)?;
+                                                    quote!{ &#owner }
+                                                }
+                                                Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, Some("u64"))?,
+                                                value_expr => ix.get_rs_arg_from_ts_arg(value_expr, false)?,
+                                            };
+                                            value_tokens.push(value_token);
+                                        }
+
+                                        let cpi_accounts = match prop {
+                                            "createAccount" => {
+                                                let (from, to) = (&accs[0], &accs[1]);
+                                                quote!{
+                                                    from: #from,
+                                                    to: #to,
+                                                }
+                                            }
+                                            "createAccountWithSeed" => {
+                                                let (from, to, base) = (&accs[0], &accs[1], &accs[2]);
+                                                quote!{
+                                                    from: #from,
+                                                    to: #to,
+                                                    base: #base,
+                                                }
+                                            }
+                                            "allocate" => {
+                                                let account = &accs[0];
+                                                quote!{
+                                                    account_to_allocate: #account,
+                                                }
+                                            }
+                                            "assign" => {
+                                                let account = &accs[0];
+                                                quote!{
+                                                    account_to_assign: #account,
+                                                }
+                                            }
+                                            _ => {
+                                                let (from, base, to) = (&accs[0], &accs[1], &accs[2]);
+                                                quote!{
+                                                    from: #from,
+                                                    base: #base,
+                                                    to: #to,
+                                                }
+                                            }
+                                        };
+
+                                        match signer_seeds {
+                                            Some(seed_tokens_vec) => {
+                                                ix_body.push(quote!{
+                                                    let signer_seeds: &[&[&[u8]]; 1] = &[&[#(#seed_tokens_vec),*]];
+                                                    let cpi_ctx = CpiContext::new_with_signer(
+                                                        ctx.accounts.system_program.to_account_info(),
+                                                        #cpi_struct {
+                                                            #cpi_accounts
+                                                        },
+                                                        signer_seeds
+                                                    );
+                                                    #cpi_fn(cpi_ctx, #(#value_tokens),*)?;
+                                                });
+                                            }
+                                            None => {
+                                                ix_body.push(quote!{
+                                                    let cpi_ctx = CpiContext::new(
+                                                        ctx.accounts.system_program.to_account_info(),
+                                                        #cpi_struct {
+                                                            #cpi_accounts
+                                                        },
+                                                    );
+                                                    #cpi_fn(cpi_ctx, #(#value_tokens),*)?;
+                                                });
+                                            }
+                                        }
                                     }
                                 }
 
-                                if obj == "TokenProgram" {
+                                if obj == "TokenProgram2022" && !ix.uses_token_interface {
+                                    return Err(anyhow!("Add a `tokenProgram: TokenInterface` parameter to {} to use TokenProgram2022", ix.name));
+                                }
+                                if obj == "TokenProgram" || obj == "TokenProgram2022" {
                                     match prop {
+                                        "transfer" if ix.uses_token_interface => {
+                                            return Err(anyhow!("Token-2022 mints need TokenProgram.transferChecked instead of transfer"));
+                                        }
                                         "transfer" => {
-                                        program_mod.add_import("anchor_spl", "token", "transfer");
-                                        program_mod.add_import("anchor_spl", "token", "Transfer");
+                                        program_mod.add_import("anchor_spl", token_mod, "transfer");
+                                        program_mod.add_import("anchor_spl", token_mod, "Transfer");
                                         let from_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                         let to_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                         let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                        let from_acc_ident = Ident::new(&from_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                        let to_acc_ident = Ident::new(&to_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                        let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                        let from_acc_ident = ix.get_rs_account(from_acc);
+                                        let to_acc_ident = ix.get_rs_account(to_acc);
+                                        let auth_acc_ident = ix.get_rs_account(auth_acc);
                                         let amount_expr = &c.args[3].expr;
                                         let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
                                         if let Some(cur_ix_acc) = ix_accounts.get(from_acc){
@@ -742,9 +2708,9 @@ impl ProgramInstruction {
                                                 };
                                                 ix_body.push(quote!{
                                                     let cpi_accounts = TransferSPL {
-                                                        from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                        to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                        authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                        from: #from_acc_ident.to_account_info(),
+                                                        to: #to_acc_ident.to_account_info(),
+                                                        authority: #auth_acc_ident.to_account_info(),
                                                     };
 
                                                     let signer_seeds: &[&[&[u8]]; 1] = #signer_var_token_stream
@@ -759,9 +2725,9 @@ impl ProgramInstruction {
                                             } else {
                                                 ix_body.push(quote!{
                                                     let cpi_accounts = TransferSPL {
-                                                        from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                        to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                        authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                        from: #from_acc_ident.to_account_info(),
+                                                        to: #to_acc_ident.to_account_info(),
+                                                        authority: #auth_acc_ident.to_account_info(),
                                                     };
                                                     let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
                                                     transfer_spl(cpi_ctx, #amount)?;
@@ -770,14 +2736,14 @@ impl ProgramInstruction {
                                         }
                                         },
                                         "burn" => {
-                                            program_mod.add_import("anchor_spl", "token", "burn");
-                                            program_mod.add_import("anchor_spl", "token", "Burn");
+                                            program_mod.add_import("anchor_spl", token_mod, "burn");
+                                            program_mod.add_import("anchor_spl", token_mod, "Burn");
                                             let mint_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let from_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let from_acc_ident = Ident::new(&from_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let from_acc_ident = ix.get_rs_account(from_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
                                             let amount_expr = &c.args[3].expr;
                                             let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
 
@@ -796,9 +2762,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             Burn {
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                from: #from_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -811,9 +2777,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             Burn {
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                from: #from_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -823,14 +2789,14 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "mintTo" => {
-                                            program_mod.add_import("anchor_spl", "token", "mint_to");
-                                            program_mod.add_import("anchor_spl", "token", "MintTo");
+                                            program_mod.add_import("anchor_spl", token_mod, "mint_to");
+                                            program_mod.add_import("anchor_spl", token_mod, "MintTo");
                                             let mint_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let to_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let to_acc_ident = Ident::new(&to_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let to_acc_ident = ix.get_rs_account(to_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
                                             let amount_expr = &c.args[3].expr;
                                             let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
 
@@ -848,9 +2814,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             MintTo {
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                to: #to_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -862,9 +2828,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             MintTo {
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                to: #to_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
                                                         mint_to(cpi_ctx, #amount)?;
@@ -873,14 +2839,14 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "approve" => {
-                                            program_mod.add_import("anchor_spl", "token", "approve");
-                                            program_mod.add_import("anchor_spl", "token", "Approve");
+                                            program_mod.add_import("anchor_spl", token_mod, "approve");
+                                            program_mod.add_import("anchor_spl", token_mod, "Approve");
                                             let to_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let delegate_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let to_acc_ident = Ident::new(&to_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let delegate_acc_ident = Ident::new(&delegate_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let to_acc_ident = ix.get_rs_account(to_acc);
+                                            let delegate_acc_ident = ix.get_rs_account(delegate_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
                                             let amount_expr = &c.args[3].expr;
 
                                             let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
@@ -899,9 +2865,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             Approve {
-                                                                to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                                delegate: ctx.accounts.#delegate_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                to: #to_acc_ident.to_account_info(),
+                                                                delegate: #delegate_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -913,9 +2879,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             Approve {
-                                                                to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                                delegate: ctx.accounts.#delegate_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                to: #to_acc_ident.to_account_info(),
+                                                                delegate: #delegate_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -925,18 +2891,16 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "approveChecked" => {
-                                              program_mod.add_import("anchor_spl", "token", "approve_checked");
-                                              program_mod.add_import("anchor_spl", "token", "ApproveChecked");
+                                              program_mod.add_import("anchor_spl", token_mod, "approve_checked");
+                                              program_mod.add_import("anchor_spl", token_mod, "ApproveChecked");
                                             let to_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let mint_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let delegate_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[3].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let to_acc_ident = Ident::new(&to_acc.to_case(Case::Snake),
-                                            proc_macro2::Span::call_site());
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake),
-                                            proc_macro2::Span::call_site());
-                                            let delegate_acc_ident = Ident::new(&delegate_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let to_acc_ident = ix.get_rs_account(to_acc);
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let delegate_acc_ident = ix.get_rs_account(delegate_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
                                             let amount_expr = &c.args[4].expr;
                                             let decimal_expr = &c.args[5].expr;
                                             let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
@@ -955,10 +2919,10 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             ApproveChecked {
-                                                                to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                delegate: ctx.accounts.#delegate_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                to: #to_acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                delegate: #delegate_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -970,10 +2934,10 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             ApproveChecked {
-                                                                to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                delegate: ctx.accounts.#delegate_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                to: #to_acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                delegate: #delegate_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -983,14 +2947,14 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "closeAccount" => {
-                                            program_mod.add_import("anchor_spl", "token", "close_account");
-                                            program_mod.add_import("anchor_spl", "token", "CloseAccount");
+                                            program_mod.add_import("anchor_spl", token_mod, "close_account");
+                                            program_mod.add_import("anchor_spl", token_mod, "CloseAccount");
                                             let acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let destination_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let acc_ident = Ident::new(&acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let destination_acc_ident = Ident::new(&destination_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let acc_ident = ix.get_rs_account(acc);
+                                            let destination_acc_ident = ix.get_rs_account(destination_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
                                             if let Some(cur_ix_acc) = ix_accounts.get(auth_acc){
                                                 if cur_ix_acc.seeds.is_some() {
                                                     let seeds = &c.args.get(3).ok_or(anyhow!("Pass the seeds array argument"))?.expr.as_array().ok_or(anyhow!("expected an array"))?.elems;
@@ -1005,9 +2969,9 @@ impl ProgramInstruction {
                                                         let close_cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             CloseAccount {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                destination: ctx.accounts.#destination_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                destination: #destination_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -1019,9 +2983,9 @@ impl ProgramInstruction {
                                                         let close_cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             CloseAccount {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                destination: ctx.accounts.#destination_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                destination: #destination_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -1032,14 +2996,14 @@ impl ProgramInstruction {
 
                                         },
                                         "freezeAccount" => {
-                                            program_mod.add_import("anchor_spl", "token", "freeze_account");
-                                            program_mod.add_import("anchor_spl", "token", "FreezeAccount");
+                                            program_mod.add_import("anchor_spl", token_mod, "freeze_account");
+                                            program_mod.add_import("anchor_spl", token_mod, "FreezeAccount");
                                             let acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let mint_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let acc_ident = Ident::new(&acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let acc_ident = ix.get_rs_account(acc);
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
 
                                             if let Some(cur_ix_acc) = ix_accounts.get(auth_acc){
                                                 if cur_ix_acc.seeds.is_some() {
@@ -1056,9 +3020,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             FreezeAccount {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -1070,9 +3034,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             FreezeAccount {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -1082,14 +3046,14 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "initializeAccount" => {
-                                            program_mod.add_import("anchor_spl", "token", "initialize_account3");
-                                            program_mod.add_import("anchor_spl", "token", "InitializeAccount3");
+                                            program_mod.add_import("anchor_spl", token_mod, "initialize_account3");
+                                            program_mod.add_import("anchor_spl", token_mod, "InitializeAccount3");
                                             let acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let mint_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let acc_ident = Ident::new(&acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let acc_ident = ix.get_rs_account(acc);
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
 
                                             if let Some(cur_ix_acc) = ix_accounts.get(auth_acc){
                                                 if cur_ix_acc.seeds.is_some() {
@@ -1106,9 +3070,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             InitializeAccount3 {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -1120,9 +3084,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             InitializeAccount3 {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -1132,12 +3096,12 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "revoke" => {
-                                            program_mod.add_import("anchor_spl", "token", "revoke");
-                                            program_mod.add_import("anchor_spl", "token", "Revoke");
+                                            program_mod.add_import("anchor_spl", token_mod, "revoke");
+                                            program_mod.add_import("anchor_spl", token_mod, "Revoke");
                                             let source_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let source_acc_ident = Ident::new(&source_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let source_acc_ident = ix.get_rs_account(source_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
 
                                             if let Some(cur_ix_acc) = ix_accounts.get(auth_acc){
                                                 if cur_ix_acc.seeds.is_some() {
@@ -1154,8 +3118,8 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             Revoke {
-                                                                source: ctx.accounts.#source_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                source: #source_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -1167,8 +3131,8 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             Revoke {
-                                                                source: ctx.accounts.#source_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                source: #source_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -1178,10 +3142,10 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "syncNative" => {
-                                            program_mod.add_import("anchor_spl", "token", "sync_native");
-                                            program_mod.add_import("anchor_spl", "token", "SyncNative");
+                                            program_mod.add_import("anchor_spl", token_mod, "sync_native");
+                                            program_mod.add_import("anchor_spl", token_mod, "SyncNative");
                                             let acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let acc_ident = Ident::new(&acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let acc_ident = ix.get_rs_account(acc);
 
                                             if let Some(cur_ix_acc) = ix_accounts.get(acc){
                                                 if cur_ix_acc.seeds.is_some() {
@@ -1198,7 +3162,7 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             SyncNative {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -1210,7 +3174,7 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             SyncNative {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -1220,14 +3184,14 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "thawAccount" => {
-                                            program_mod.add_import("anchor_spl", "token", "thaw_account");
-                                            program_mod.add_import("anchor_spl", "token", "ThawAccount");
+                                            program_mod.add_import("anchor_spl", token_mod, "thaw_account");
+                                            program_mod.add_import("anchor_spl", token_mod, "ThawAccount");
                                             let acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let mint_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let acc_ident = Ident::new(&acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let acc_ident = ix.get_rs_account(acc);
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
 
                                             if let Some(cur_ix_acc) = ix_accounts.get(acc){
                                                 if cur_ix_acc.seeds.is_some() {
@@ -1244,9 +3208,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new_with_signer(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             ThawAccount {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                             signer_seeds
                                                         );
@@ -1258,9 +3222,9 @@ impl ProgramInstruction {
                                                         let cpi_ctx = CpiContext::new(
                                                             ctx.accounts.token_program.to_account_info(),
                                                             ThawAccount {
-                                                                account: ctx.accounts.#acc_ident.to_account_info(),
-                                                                mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                                authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                                account: #acc_ident.to_account_info(),
+                                                                mint: #mint_acc_ident.to_account_info(),
+                                                                authority: #auth_acc_ident.to_account_info(),
                                                             },
                                                         );
 
@@ -1270,16 +3234,16 @@ impl ProgramInstruction {
                                             }
                                         },
                                         "transferChecked" => {
-                                            program_mod.add_import("anchor_spl", "token", "transfer_checked");
-                                            program_mod.add_import("anchor_spl", "token", "TransferChecked");
+                                            program_mod.add_import("anchor_spl", token_mod, "transfer_checked");
+                                            program_mod.add_import("anchor_spl", token_mod, "TransferChecked");
                                             let from_acc = c.args[0].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let mint_acc = c.args[1].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let to_acc = c.args[2].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let auth_acc = c.args[3].expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            let from_acc_ident = Ident::new(&from_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let mint_acc_ident = Ident::new(&mint_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let to_acc_ident = Ident::new(&to_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                            let auth_acc_ident = Ident::new(&auth_acc.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            let from_acc_ident = ix.get_rs_account(from_acc);
+                                            let mint_acc_ident = ix.get_rs_account(mint_acc);
+                                            let to_acc_ident = ix.get_rs_account(to_acc);
+                                            let auth_acc_ident = ix.get_rs_account(auth_acc);
                                             let amount_expr = &c.args[4].expr;
                                             let decimal_expr = &c.args[5].expr;
                                             let amount = ix.get_rs_arg_from_ts_arg(&amount_expr, false)?;
@@ -1295,10 +3259,10 @@ impl ProgramInstruction {
                                                     };
                                                     ix_body.push(quote!{
                                                         let cpi_accounts = TransferChecked {
-                                                            from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                            mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                            to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                            authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                            from: #from_acc_ident.to_account_info(),
+                                                            mint: #mint_acc_ident.to_account_info(),
+                                                            to: #to_acc_ident.to_account_info(),
+                                                            authority: #auth_acc_ident.to_account_info(),
                                                         };
                                                         let signer_seeds: &[&[&[u8]]; 1] = #signer_var_token_stream
                                                         let ctx = CpiContext::new_with_signer(
@@ -1311,18 +3275,247 @@ impl ProgramInstruction {
                                                 } else {
                                                     ix_body.push(quote!{
                                                         let cpi_accounts = TransferChecked {
-                                                            from: ctx.accounts.#from_acc_ident.to_account_info(),
-                                                            mint: ctx.accounts.#mint_acc_ident.to_account_info(),
-                                                            to: ctx.accounts.#to_acc_ident.to_account_info(),
-                                                            authority: ctx.accounts.#auth_acc_ident.to_account_info(),
+                                                            from: #from_acc_ident.to_account_info(),
+                                                            mint: #mint_acc_ident.to_account_info(),
+                                                            to: #to_acc_ident.to_account_info(),
+                                                            authority: #auth_acc_ident.to_account_info(),
                                                         };
                                                         let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
                                                         transfer_checked(cpi_ctx, #amount, #decimal)?;
                                                     })
                                                 }
                                             }
-                                        },
-                                        _ => {}
+                                        },
+                                        _ => {}
+                                    }
+                                }
+                                if let Some(external) = program_mod.external_programs.iter().find(|p| p.name == obj).cloned() {
+                                    let method = external.get_method(prop).ok_or(anyhow!("{}.{} is not declared", obj, prop))?;
+                                    let (cpi_args, signer_seeds) = match c.args.last().map(|a| a.expr.as_ref()) {
+                                        Some(Expr::Array(seeds)) => (&c.args[..c.args.len() - 1], Some(ix.get_seeds(&seeds.elems, true)?)),
+                                        _ => (&c.args[..], None),
+                                    };
+                                    if cpi_args.len() != method.accounts.len() + method.args.len() {
+                                        return Err(anyhow!("{}.{} expects {} arguments besides the signer seeds", obj, prop, method.accounts.len() + method.args.len()));
+                                    }
+                                    let external_program = Ident::new(&obj.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                    if !ix.external_programs.contains(&obj.to_string()) {
+                                        ix.external_programs.push(obj.to_string());
+                                    }
+                                    let cpi_fn = Ident::new(&prop.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                    let cpi_struct = Ident::new(&prop.to_case(Case::Pascal), proc_macro2::Span::call_site());
+
+                                    // the fields of the generated accounts struct are named after the declared parameters
+                                    let mut cpi_accounts: Vec<TokenStream> = vec![];
+                                    for (field, arg) in method.accounts.iter().zip(cpi_args.iter()) {
+                                        let acc_info = ix.get_rs_account_info_from_ts_expr(&arg.expr)?;
+                                        let field = Ident::new(&field.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                        cpi_accounts.push(quote!{ #field: #acc_info });
+                                    }
+                                    let mut values: Vec<TokenStream> = vec![];
+                                    for arg in cpi_args[method.accounts.len()..].iter() {
+                                        let value = match arg.expr.as_ref() {
+                                            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, None)?,
+                                            Expr::Lit(Lit::Str(s)) => {
+                                                let value_literal = Literal::string(&s.value);
+                                                quote!{ #value_literal.to_string() }
+                                            }
+                                            value_expr => ix.get_rs_arg_from_ts_arg(value_expr, false)?,
+                                        };
+                                        values.push(value);
+                                    }
+
+                                    match signer_seeds {
+                                        Some(seed_tokens_vec) => {
+                                            ix_body.push(quote!{
+                                                let signer_seeds: &[&[&[u8]]; 1] = &[&[#(#seed_tokens_vec),*]];
+                                                let cpi_ctx = CpiContext::new_with_signer(
+                                                    ctx.accounts.#external_program.to_account_info(),
+                                                    #external_program::cpi::accounts::#cpi_struct {
+                                                        #(#cpi_accounts),*
+                                                    },
+                                                    signer_seeds
+                                                );
+                                                #external_program::cpi::#cpi_fn(cpi_ctx, #(#values),*)?;
+                                            });
+                                        }
+                                        None => {
+                                            ix_body.push(quote!{
+                                                let cpi_ctx = CpiContext::new(
+                                                    ctx.accounts.#external_program.to_account_info(),
+                                                    #external_program::cpi::accounts::#cpi_struct {
+                                                        #(#cpi_accounts),*
+                                                    },
+                                                );
+                                                #external_program::cpi::#cpi_fn(cpi_ctx, #(#values),*)?;
+                                            });
+                                        }
+                                    }
+                                }
+                                if obj == "MetadataProgram" {
+                                    // accounts first, then the metadata values, then optional signer seeds
+                                    let (account_count, cpi_fn, cpi_struct) = match prop {
+                                        "createMetadataAccountsV3" => (5, "create_metadata_accounts_v3", "CreateMetadataAccountsV3"),
+                                        "createMasterEditionV3" => (6, "create_master_edition_v3", "CreateMasterEditionV3"),
+                                        "updateMetadataAccountsV2" => (2, "update_metadata_accounts_v2", "UpdateMetadataAccountsV2"),
+                                        _ => return Err(anyhow!("MetadataProgram.{} is not supported", prop)),
+                                    };
+                                    ix.uses_metadata_program = true;
+                                    program_mod.add_import("anchor_spl", "metadata", "Metadata");
+                                    program_mod.add_import("anchor_spl", "metadata", cpi_fn);
+                                    program_mod.add_import("anchor_spl", "metadata", cpi_struct);
+                                    let cpi_fn = Ident::new(cpi_fn, proc_macro2::Span::call_site());
+                                    let cpi_struct = Ident::new(cpi_struct, proc_macro2::Span::call_site());
+
+                                    let (cpi_args, signer_seeds) = match c.args.last().map(|a| a.expr.as_ref()) {
+                                        Some(Expr::Array(seeds)) => (&c.args[..c.args.len() - 1], Some(ix.get_seeds(&seeds.elems, true)?)),
+                                        _ => (&c.args[..], None),
+                                    };
+                                    if cpi_args.len() < account_count {
+                                        return Err(anyhow!("MetadataProgram.{} expects {} accounts", prop, account_count));
+                                    }
+                                    let mut accs: Vec<TokenStream> = vec![];
+                                    for (index, arg) in cpi_args[..account_count].iter().enumerate() {
+                                        let acc = arg.expr.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                        // the metadata program writes the metadata and edition accounts
+                                        let is_written = match prop {
+                                            "createMasterEditionV3" => index == 0 || index == 5,
+                                            _ => index == 0,
+                                        };
+                                        if is_written {
+                                            if let Some(written_acc) = ix_accounts.get_mut(acc) {
+                                                written_acc.is_mut = true;
+                                            }
+                                        }
+                                        accs.push(ix.get_rs_account(acc));
+                                    }
+                                    let values = &cpi_args[account_count..];
+
+                                    let mut data_token = quote!{};
+                                    if prop != "createMasterEditionV3" {
+                                        program_mod.add_import("anchor_spl", "metadata", "mpl_token_metadata");
+                                        if values.len() != 4 {
+                                            return Err(anyhow!("MetadataProgram.{} expects name, symbol, uri and seller fee basis points", prop));
+                                        }
+                                        let mut strings: Vec<TokenStream> = vec![];
+                                        for value in values[..3].iter() {
+                                            match value.expr.as_ref() {
+                                                Expr::Lit(Lit::Str(s)) => {
+                                                    let s_literal = Literal::string(&s.value);
+                                                    strings.push(quote!{ #s_literal.to_string() });
+                                                }
+                                                value_expr => {
+                                                    let value = ix.get_rs_arg_from_ts_arg(value_expr, false)?;
+                                                    strings.push(quote!{ #value.to_string() });
+                                                }
+                                            }
+                                        }
+                                        let (name, symbol, uri) = (&strings[0], &strings[1], &strings[2]);
+                                        let fee = match values[3].expr.as_ref() {
+                                            Expr::Lit(lit @ Lit::Num(_)) => rs_int_literal(lit, Some("u16"))?,
+                                            fee_expr => ix.get_rs_arg_from_ts_arg(fee_expr, false)?,
+                                        };
+                                        data_token = quote!{
+                                            mpl_token_metadata::types::DataV2 {
+                                                name: #name,
+                                                symbol: #symbol,
+                                                uri: #uri,
+                                                seller_fee_basis_points: #fee,
+                                                creators: None,
+                                                collection: None,
+                                                uses: None,
+                                            }
+                                        };
+                                    }
+
+                                    let (cpi_accounts, cpi_call_args) = match prop {
+                                        "createMetadataAccountsV3" => {
+                                            ix.uses_system_program = true;
+                                            ix.uses_rent_sysvar = true;
+                                            let (metadata, mint, mint_authority, payer, update_authority) = (&accs[0], &accs[1], &accs[2], &accs[3], &accs[4]);
+                                            (
+                                                quote!{
+                                                    metadata: #metadata.to_account_info(),
+                                                    mint: #mint.to_account_info(),
+                                                    mint_authority: #mint_authority.to_account_info(),
+                                                    payer: #payer.to_account_info(),
+                                                    update_authority: #update_authority.to_account_info(),
+                                                    system_program: ctx.accounts.system_program.to_account_info(),
+                                                    rent: ctx.accounts.rent.to_account_info(),
+                                                },
+                                                quote!{ #data_token, true, true, None },
+                                            )
+                                        }
+                                        "createMasterEditionV3" => {
+                                            ix.uses_system_program = true;
+                                            ix.uses_rent_sysvar = true;
+                                            ix.uses_token_program = true;
+                                            if !ix.uses_token_interface {
+                                                program_mod.add_import("anchor_spl", "token", "Token");
+                                            }
+                                            let (edition, mint, update_authority, mint_authority, payer, metadata) = (&accs[0], &accs[1], &accs[2], &accs[3], &accs[4], &accs[5]);
+                                            let max_supply = match values.first() {
+                                                Some(max_supply) => {
+                                                    let max_supply = match max_supply.expr.as_ref() {
+                                                        Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, Some("u64"))?,
+                                                        max_supply_expr => ix.get_rs_arg_from_ts_arg(max_supply_expr, false)?,
+                                                    };
+                                                    quote!{ Some(#max_supply) }
+                                                }
+                                                None => quote!{ None },
+                                            };
+                                            (
+                                                quote!{
+                                                    edition: #edition.to_account_info(),
+                                                    mint: #mint.to_account_info(),
+                                                    update_authority: #update_authority.to_account_info(),
+                                                    mint_authority: #mint_authority.to_account_info(),
+                                                    payer: #payer.to_account_info(),
+                                                    metadata: #metadata.to_account_info(),
+                                                    token_program: ctx.accounts.token_program.to_account_info(),
+                                                    system_program: ctx.accounts.system_program.to_account_info(),
+                                                    rent: ctx.accounts.rent.to_account_info(),
+                                                },
+                                                quote!{ #max_supply },
+                                            )
+                                        }
+                                        _ => {
+                                            let (metadata, update_authority) = (&accs[0], &accs[1]);
+                                            (
+                                                quote!{
+                                                    metadata: #metadata.to_account_info(),
+                                                    update_authority: #update_authority.to_account_info(),
+                                                },
+                                                quote!{ None, Some(#data_token), None, None },
+                                            )
+                                        }
+                                    };
+
+                                    match signer_seeds {
+                                        Some(seed_tokens_vec) => {
+                                            ix_body.push(quote!{
+                                                let signer_seeds: &[&[&[u8]]; 1] = &[&[#(#seed_tokens_vec),*]];
+                                                let cpi_ctx = CpiContext::new_with_signer(
+                                                    ctx.accounts.metadata_program.to_account_info(),
+                                                    #cpi_struct {
+                                                        #cpi_accounts
+                                                    },
+                                                    signer_seeds
+                                                );
+                                                #cpi_fn(cpi_ctx, #cpi_call_args)?;
+                                            });
+                                        }
+                                        None => {
+                                            ix_body.push(quote!{
+                                                let cpi_ctx = CpiContext::new(
+                                                    ctx.accounts.metadata_program.to_account_info(),
+                                                    #cpi_struct {
+                                                        #cpi_accounts
+                                                    },
+                                                );
+                                                #cpi_fn(cpi_ctx, #cpi_call_args)?;
+                                            });
+                                        }
                                     }
                                 }
                             }
@@ -1334,30 +3527,68 @@ impl ProgramInstruction {
                                 if ix_accounts.contains_key(left_obj){
                                     let left_obj_ident = Ident::new(&left_obj.to_case(Case::Snake), proc_macro2::Span::call_site());
                                     let left_prop_ident = Ident::new(&left_prop.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                    let left = ix.account_field(&left_obj_ident, &left_prop_ident)?;
                                     let cur_acc = ix_accounts.get_mut(left_obj).unwrap();
                                     cur_acc.is_mut = true;
+                                    // `acc.flags |= mask` and the other compound assignments
+                                    if a.op != AssignOp::Assign {
+                                        let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                        let op = match a.op {
+                                            AssignOp::AddAssign => quote!{ += },
+                                            AssignOp::SubAssign => quote!{ -= },
+                                            AssignOp::MulAssign => quote!{ *= },
+                                            AssignOp::DivAssign => quote!{ /= },
+                                            AssignOp::ModAssign => quote!{ %= },
+                                            AssignOp::BitAndAssign => quote!{ &= },
+                                            AssignOp::BitOrAssign => quote!{ |= },
+                                            AssignOp::BitXorAssign => quote!{ ^= },
+                                            AssignOp::LShiftAssign => quote!{ <<= },
+                                            AssignOp::RShiftAssign => quote!{ >>= },
+                                            op => return Err(anyhow!("unsupported assignment operator {}", op)),
+                                        };
+                                        ix_body.push(quote!{
+                                            #left #op #right;
+                                        });
+                                        return Ok(());
+                                    }
+                                    let sysvar_right = ix.get_rs_sysvar_from_ts_expr(&a.right, false)?;
                                     match *(a.clone().right) {
+                                        // `Clock.get().unixTimestamp` and the other sysvar reads
+                                        _ if sysvar_right.is_some() => {
+                                            let right = sysvar_right.ok_or(anyhow!("expected a sysvar"))?;
+                                            ix_body.push(quote!{
+                                                #left = #right;
+                                            });
+                                        }
+                                        // operators and literals, e.g. `acc.flags | mask` or `acc.count < acc.max`
+                                        Expr::Bin(_) | Expr::Unary(_) | Expr::Paren(_) | Expr::Lit(_) => {
+                                            let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                            ix_body.push(quote!{
+                                                #left = #right;
+                                            });
+                                        }
+                                        Expr::New(exp) if exp.callee.as_ident().is_some_and(|c| c.sym == "Pubkey") => {
+                                            let right = ix.get_rs_pubkey_in_body(program_mod, &ix_accounts, &a.right)?;
+                                            ix_body.push(quote!{
+                                                #left = #right;
+                                            });
+                                        }
                                         Expr::New(exp) => {
-                                            let right_lit  = exp.args.ok_or(anyhow!("need some value in  new expression"))?[0].expr.clone().expect_lit();
-                                            let _lit_type = exp.callee.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                            match right_lit {
-                                                Lit::Num(num) => {
-                                                    // match lit_type {
-                                                    //     TsType::I64 => {
-                                                    //     }
-                                                    // }
-                                                    let value = Literal::i64_unsuffixed(num.value as i64);
-                                                    ix_body.push(quote!{
-                                                        ctx.accounts.#left_obj_ident.#left_prop_ident =  #value;
-                                                    });
-                                                }
-                                                _ => {}
-                                            }
+                                            let value = exp.args.as_ref().and_then(|args| args.first()).ok_or(anyhow!("need some value in  new expression"))?;
+                                            let lit_type = exp.callee.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                            let value = match value.expr.as_ref() {
+                                                Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => rs_int_literal(lit, Some(lit_type))?,
+                                                // `new i64(-1)`
+                                                value => ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, value)?,
+                                            };
+                                            ix_body.push(quote!{
+                                                #left =  #value;
+                                            });
                                         },
                                         Expr::Ident(right_swc_ident) => {
                                             let right_ident = Ident::new(&right_swc_ident.sym.as_ref().to_case(Case::Snake), proc_macro2::Span::call_site());
                                             ix_body.push(quote!{
-                                                ctx.accounts.#left_obj_ident.#left_prop_ident = #right_ident;
+                                                #left = #right_ident;
                                             });
                                         },
                                         Expr::Call(CallExpr { span: _, callee, args, type_args: _ }) => {
@@ -1365,73 +3596,94 @@ impl ProgramInstruction {
                                             let prop: &str = &memebers.prop.as_ident().ok_or(anyhow!("expected a prop"))?.sym.as_ref();
                                             match *memebers.obj {
                                                 Expr::Member(sub_members) => {
-                                                    let sub_prop = sub_members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                                    let sub_obj = sub_members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
-                                                    let right_sub_obj_ident = Ident::new(&sub_obj.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                                    let right_sub_prop_ident = Ident::new(&sub_prop.to_case(Case::Snake), proc_macro2::Span::call_site());
-                                                    match *(args[0].expr.clone()) {
-                                                        Expr::Lit(Lit::Num(num)) => {
-                                                            let value = Literal::i64_unsuffixed(num.value as i64);
-                                                            match prop {
-                                                                "add" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident + #value;
-                                                                    });
-                                                                },
-                                                                "sub" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident - #value;
-                                                                    });
-                                                                },
-                                                                "mul" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident * #value;
-                                                                    });
-                                                                },
-                                                                "div" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident / #value;
-                                                                    });
-                                                                },
-                                                                "eq" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident == #value;
-                                                                    });
-                                                                },
-                                                                "neq" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident != #value;
-                                                                    });
-                                                                },
-                                                                "lt" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident < #value;
-                                                                    });
-                                                                },
-                                                                "lte" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident <= #value;
-                                                                    });
-                                                                },
-                                                                "gt" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident > #value;
-                                                                    });
-                                                                },
-                                                                "gte" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident >= #value;
-                                                                    });
-                                                                },
-                                                                "toBytes" => {
-                                                                    ix_body.push(quote!{
-                                                                        ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.accounts.#right_sub_obj_ident.#right_sub_prop_ident.to_bytes();
-                                                                    });
-                                                                },
-                                                                _ => {}
+                                                    let right = match ix.get_rs_sysvar_from_ts_expr(&Expr::Member(sub_members.clone()), false)? {
+                                                        Some(sysvar) => sysvar,
+                                                        None => {
+                                                            let sub_prop = sub_members.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                                            let sub_obj = sub_members.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
+                                                            let right_sub_obj_ident = Ident::new(&sub_obj.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                                            let right_sub_prop_ident = Ident::new(&sub_prop.to_case(Case::Snake), proc_macro2::Span::call_site());
+                                                            ix.account_field(&right_sub_obj_ident, &right_sub_prop_ident)?
+                                                        }
+                                                    };
+                                                    let value = match args.first().map(|arg| arg.expr.as_ref()) {
+                                                        Some(Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_)))) => Some(rs_int_literal(lit, None)?),
+                                                        Some(arg) => ix.get_constant(arg).map(|c| {
+                                                            let name = c.ident();
+                                                            quote!{ #name }
+                                                        }),
+                                                        // `acc.bump.toU64()` and other calls without arguments
+                                                        None => None,
+                                                    };
+                                                    if let Some(value) = value {
+                                                        match prop {
+                                                            "add" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right + #value;
+                                                                });
+                                                            },
+                                                            "sub" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right - #value;
+                                                                });
+                                                            },
+                                                            "mul" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right * #value;
+                                                                });
+                                                            },
+                                                            "div" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right / #value;
+                                                                });
+                                                            },
+                                                            "eq" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right == #value;
+                                                                });
+                                                            },
+                                                            "neq" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right != #value;
+                                                                });
+                                                            },
+                                                            "lt" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right < #value;
+                                                                });
+                                                            },
+                                                            "lte" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right <= #value;
+                                                                });
+                                                            },
+                                                            "gt" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right > #value;
+                                                                });
+                                                            },
+                                                            "gte" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right >= #value;
+                                                                });
+                                                            },
+                                                            "toBytes" => {
+                                                                ix_body.push(quote!{
+                                                                    #left = #right.to_bytes();
+                                                                });
+                                                            },
+                                                            _ => {
+                                                                let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                                                ix_body.push(quote!{
+                                                                    #left = #right;
+                                                                });
                                                             }
                                                         }
-                                                        _ => {}
+                                                    } else {
+                                                        let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                                        ix_body.push(quote!{
+                                                            #left = #right;
+                                                        });
                                                     }
                                                 }
                                                 Expr::Ident(right_obj) => {
@@ -1440,25 +3692,64 @@ impl ProgramInstruction {
                                                     if right_prop == "getBump" {
                                                         let right_obj_ident = Ident::new(&right_obj.to_case(Case::Snake), proc_macro2::Span::call_site());
                                                         ix_body.push(quote!{
-                                                            ctx.accounts.#left_obj_ident.#left_prop_ident = ctx.bumps.#right_obj_ident;
+                                                            #left = ctx.bumps.#right_obj_ident;
                                                         })
+                                                    } else {
+                                                        let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                                        ix_body.push(quote!{
+                                                            #left = #right;
+                                                        });
                                                     }
                                                 }
-                                                _ => {}
+                                                _ => {
+                                                    let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                                    ix_body.push(quote!{
+                                                        #left = #right;
+                                                    });
+                                                }
                                             }
                                         }
+                                        Expr::Member(_) if ix.get_constant(&a.right).is_some() => {
+                                            let constant = ix.get_constant(&a.right).unwrap();
+                                            let name = constant.ident();
+                                            let right = match constant.of_type.as_str() {
+                                                "Str" => quote!{ #name.to_string() },
+                                                _ => quote!{ #name },
+                                            };
+                                            ix_body.push(quote!{
+                                                #left = #right;
+                                            });
+                                        }
+                                        // `Context.remainingAccounts.length` or `Context.remainingAccounts[i].key`
+                                        Expr::Member(m) if is_remaining_accounts(&m.obj) || ix.is_remaining_account(&m.obj) => {
+                                            let right = match is_remaining_accounts(&m.obj) {
+                                                true => ix.get_rs_arg_from_ts_arg(&a.right, false)?,
+                                                false => ix.get_rs_pubkey_in_body(program_mod, &ix_accounts, &a.right)?,
+                                            };
+                                            ix_body.push(quote!{
+                                                #left = #right;
+                                            });
+                                        }
+                                        Expr::Member(_) if lamports_account(&ix_accounts, custom_accounts, &a.right).is_some() => {
+                                            let right = ix.get_rs_expr_from_ts_expr(program_mod, &ix_accounts, custom_accounts, &a.right)?;
+                                            ix_body.push(quote!{
+                                                #left = #right;
+                                            });
+                                        }
                                         Expr::Member(m) => {
                                             let right_obj = m.obj.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let right_prop = m.prop.as_ident().ok_or(PoseidonError::IdentNotFound)?.sym.as_ref();
                                             let right_obj_ident = Ident::new(&right_obj.to_case(Case::Snake), proc_macro2::Span::call_site());
                                             let right_prop_ident = Ident::new(&right_prop.to_case(Case::Snake), proc_macro2::Span::call_site());
                                             if ix_accounts.get(right_obj).is_some() && right_prop == "key" {
+                                                let right_acc = ix.get_rs_account(right_obj);
                                                 ix_body.push(quote!{
-                                                    ctx.accounts.#left_obj_ident.#left_prop_ident =  ctx.accounts.#right_obj_ident.key();
+                                                    #left =  #right_acc.key();
                                                 });
                                             } else {
+                                                let right = ix.account_field(&right_obj_ident, &right_prop_ident)?;
                                                 ix_body.push(quote!{
-                                                    ctx.accounts.#left_obj_ident.#left_prop_ident =  ctx.accounts.#right_obj_ident.#right_prop_ident;
+                                                    #left =  #right;
                                                 });
                                             }
                                         }
@@ -1469,7 +3760,39 @@ impl ProgramInstruction {
                             _ => {}
                         }
                     },
-                    Stmt::Decl(_d) => {
+                    Stmt::Decl(d) => {
+                        // `const ix = new Instruction(...)`, built once and passed to `invoke`
+                        if let Some(var) = d.as_var() {
+                            for decl in var.decls.iter() {
+                                let Some(init) = decl.init.as_deref() else { continue };
+                                // `const [pda, bump] = Pubkey.findProgramAddress([...], programId)`
+                                if let Some((seeds, program_id)) = find_program_address_call(init) {
+                                    let names = decl.name.as_array().ok_or(anyhow!("destructure Pubkey.findProgramAddress as const [pda, bump]"))?;
+                                    let names: Vec<TokenStream> = (0..2).map(|i| match names.elems.get(i).and_then(|e| e.as_ref()) {
+                                        Some(Pat::Ident(b)) if b.id.sym != "_" => {
+                                            let name = Ident::new(&b.id.sym.as_ref().to_case(Case::Snake), proc_macro2::Span::call_site());
+                                            quote!{ #name }
+                                        }
+                                        _ => quote!{ _ },
+                                    }).collect();
+                                    let seeds = ix.get_rs_pda_seeds(program_mod, &ix_accounts, custom_accounts, seeds)?;
+                                    let program_id = ix.get_rs_pubkey_in_body(program_mod, &ix_accounts, program_id)?;
+                                    ix_body.push(quote!{
+                                        let (#(#names),*) = Pubkey::find_program_address(&[#(#seeds),*], &#program_id);
+                                    });
+                                    continue;
+                                }
+                                let is_instruction = init.as_new().and_then(|n| n.callee.as_ident()).is_some_and(|c| c.sym == "Instruction");
+                                if !is_instruction {
+                                    continue;
+                                }
+                                let var_name = Ident::new(&decl.name.as_ident().ok_or(PoseidonError::IdentNotFound)?.id.sym.as_ref().to_case(Case::Snake), proc_macro2::Span::call_site());
+                                let instruction = ix.get_rs_instruction_from_ts_expr(program_mod, &mut ix_accounts, init)?;
+                                ix_body.push(quote!{
+                                    let #var_name = #instruction;
+                                });
+                            }
+                        }
                         // let kind  = d.clone().expect_var().kind;
                         // let decls = &d.clone().expect_var().decls[0];
                         // let name = decls.name.clone().expect_ident().id.sym.to_string().to_case(Case::Snake);
@@ -1483,11 +3806,22 @@ impl ProgramInstruction {
                         //     }
                         // }
                     }
+                    Stmt::Break(_) => ix_body.push(quote!{ break; }),
+                    Stmt::Continue(_) => ix_body.push(quote!{ continue; }),
                     _ => {}
                 }
                 Ok(())
             }).collect::<Result<Vec<()>>>()?;
 
+        for (ts_name, acc) in ix_accounts.iter().filter(|(_, acc)| acc.optional) {
+            if ix_body.iter().any(|stmt| references_account(stmt, &acc.name)) {
+                return Err(anyhow!("optional account {} can only be used inside `if ({}) {{ ... }}`", ts_name, ts_name));
+            }
+        }
+
+        body_guards.resize(ix_body.len(), std::mem::take(&mut ix.zero_copy_uses));
+        let ix_body = scope_zero_copy_guards(&ix_accounts, ix_body, body_guards)?;
+
         ix.accounts = ix_accounts.into_values().collect();
         ix.body = ix_body;
 
@@ -1514,8 +3848,13 @@ impl ProgramInstruction {
             .collect();
         let body = self.body.clone();
         let stmts = quote! {#(#body)*};
+        // remaining accounts only share a lifetime with `ctx.accounts` when it is spelled out
+        let (generics, ctx_type) = match self.uses_remaining_accounts {
+            true => (quote! { <'info> }, quote! { Context<'_, '_, '_, 'info, #ctx_name<'info>> }),
+            false => (quote! {}, quote! { Context<#ctx_name> }),
+        };
         quote! {
-            pub fn #name (ctx: Context<#ctx_name>, #(#args)*) -> Result<()> {
+            pub fn #name #generics (ctx: #ctx_type, #(#args),*) -> Result<()> {
                 #stmts
                 Ok(())
 
@@ -1528,7 +3867,14 @@ impl ProgramInstruction {
             &format!("{}Context", &self.name.to_case(Case::Pascal)),
             proc_macro2::Span::call_site(),
         );
-        let mut accounts: Vec<TokenStream> = self.accounts.iter().map(|a| a.to_tokens()).collect();
+        // accounts being created come first, so accounts after them can use them in constraints
+        let mut ordered: Vec<&InstructionAccount> = self.accounts.iter().collect();
+        ordered.sort_by_key(|a| match (a.is_init, a.is_initifneeded) {
+            (true, false) => 0,
+            (_, true) => 1,
+            _ => 2,
+        });
+        let mut accounts: Vec<TokenStream> = ordered.iter().map(|a| a.to_tokens()).collect();
 
         let ix_attributes = match &self.instruction_attributes {
             Some(s) => {
@@ -1543,16 +3889,40 @@ impl ProgramInstruction {
                 pub associated_token_program: Program<'info, AssociatedToken>,
             })
         }
-        if self.uses_token_program {
+        if self.uses_token_interface {
+            accounts.push(quote! {
+                pub token_program: Interface<'info, TokenInterface>,
+            })
+        } else if self.uses_token_program {
             accounts.push(quote! {
                 pub token_program: Program<'info, Token>,
             })
         }
+        if self.uses_metadata_program {
+            accounts.push(quote! {
+                pub metadata_program: Program<'info, Metadata>,
+            })
+        }
+        for external_program in self.external_programs.iter() {
+            let program_name = Ident::new(
+                &external_program.to_case(Case::Snake),
+                proc_macro2::Span::call_site(),
+            );
+            let program_type = Ident::new(external_program, proc_macro2::Span::call_site());
+            accounts.push(quote! {
+                pub #program_name: Program<'info, #program_name::program::#program_type>,
+            })
+        }
         if self.uses_system_program {
             accounts.push(quote! {
                 pub system_program: Program<'info, System>,
             })
         }
+        if self.uses_rent_sysvar {
+            accounts.push(quote! {
+                pub rent: Sysvar<'info, Rent>,
+            })
+        }
         let info_token_stream = match accounts.is_empty() {
             true => quote! {},
             false => quote! {<'info>},
@@ -1566,3 +3936,754 @@ impl ProgramInstruction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::transpiler::transpile_source;
+
+    const ACCOUNTS: &str = r#"
+        export interface Vault extends Account {
+          admin: Pubkey;
+          amount: u64;
+          locked: Boolean;
+          bump: u8;
+        }
+
+        export enum VaultError {
+          Empty = "The vault is empty",
+          Locked,
+        }
+
+        export interface Book extends ZeroCopyAccount {
+          authority: Pubkey;
+          bids: Array<u64, 32>;
+          lastPrice: u64;
+          count: u32;
+          bump: u8;
+          padding: Array<u8, 3>;
+        }
+    "#;
+
+    fn transpile_program(body: &str) -> String {
+        try_transpile_program(body).unwrap()
+    }
+
+    fn try_transpile_program(body: &str) -> anyhow::Result<String> {
+        let src = format!(
+            r#"import {{ Account, Array, Pubkey, Result, Signer, SystemAccount, SystemProgram, u32, u64, u8, ZeroCopyAccount }} from "@solanaturbine/poseidon";
+            export default class TestProgram {{
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+              {}
+            }}
+            {}"#,
+            body, ACCOUNTS
+        );
+        transpile_source(&src)
+    }
+
+    #[test]
+    fn constraint_with_error_code() {
+        let rs = transpile_program(
+            r#"withdraw(admin: Signer, vault: Vault, fee: u64): Result {
+                vault.constraint(vault.amount.gt(0), VaultError.Empty)
+                  .constraint(vault.admin === admin.key && !vault.locked, VaultError.Locked);
+                vault.amount = fee;
+            }"#,
+        );
+        assert!(rs.contains("        constraint = vault.amount > 0 @ VaultError::Empty,\n"));
+        assert!(rs.contains(
+            "        constraint = vault.admin == admin.key() && !vault.locked @ VaultError::Locked,\n"
+        ));
+    }
+
+    #[test]
+    fn short_constraint_stays_on_one_line() {
+        let rs = transpile_program(
+            r#"withdraw(admin: Signer, vault: Vault, fee: u64): Result {
+                vault.constraint(vault.amount.sub(fee).gte(0));
+            }"#,
+        );
+        assert!(rs.contains("    #[account(constraint = vault.amount - fee >= 0)]\n"));
+    }
+
+    #[test]
+    fn sysvar_calls_in_long_constraints_keep_empty_parentheses() {
+        let rs = transpile_program(
+            r#"unlock(admin: Signer, vault: Vault): Result {
+                vault.constraint(Clock.get().slot > vault.amount && EpochSchedule.get().slotsPerEpoch > 0);
+                vault.amount = Rent.get().minimumBalance(0);
+            }"#,
+        );
+        assert!(rs.contains(
+            "        constraint = Clock::get()?.slot > vault.amount\n            && EpochSchedule::get()?.slots_per_epoch > 0,\n"
+        ));
+        assert!(rs.contains("ctx.accounts.vault.amount = Rent::get()?.minimum_balance(0);"));
+    }
+
+    #[test]
+    fn bitwise_and_comparison_operators() {
+        let rs = transpile_program(
+            r#"static readonly SEP = "a = = b";
+            static readonly FROZEN: u64 = 2;
+            update(admin: Signer, vault: Vault, mask: u64, shift: u8): Result {
+                vault.constraint((vault.amount & TestProgram.FROZEN) == 0 && !vault.locked);
+                vault.amount = (vault.amount ^ mask) << shift;
+                vault.amount &= ~mask;
+                vault.locked = vault.admin == admin.key || vault.amount != 0;
+            }"#,
+        );
+        assert!(rs.contains(r#"pub const SEP: &str = "a = = b";"#));
+        assert!(rs.contains("constraint = (vault.amount & FROZEN) == 0 && !vault.locked"));
+        assert!(rs.contains("ctx.accounts.vault.amount = (ctx.accounts.vault.amount ^ mask) << shift;"));
+        assert!(rs.contains("ctx.accounts.vault.amount &= !mask;"));
+        assert!(rs.contains("= ctx.accounts.vault.admin == ctx.accounts.admin.key()"));
+        assert!(rs.contains("|| ctx.accounts.vault.amount != 0;"));
+    }
+
+    #[test]
+    fn zero_copy_guard_is_dropped_before_cpis() {
+        let rs = transpile_program(
+            r#"deposit(payer: Signer, book: Book, price: u64): Result {
+                book.derive(["book", payer.key]);
+                book.lastPrice = price;
+                SystemProgram.transfer(payer, book, price);
+                for (let i = 0; i < book.count; i++) {
+                  book.lastPrice = book.lastPrice.add(price);
+                }
+                book.count = book.count.add(1);
+            }"#,
+        );
+        let body = "        let mut book = ctx.accounts.book.load_mut()?;
+        book.last_price = price;
+        drop(book);
+        let transfer_accounts = Transfer {
+            from: ctx.accounts.payer.to_account_info(),
+            to: ctx.accounts.book.to_account_info(),
+        };";
+        assert!(rs.contains(body));
+        let body = "        transfer(cpi_ctx, price)?;
+        let mut book = ctx.accounts.book.load_mut()?;
+        for i in 0..book.count {
+            book.last_price = book.last_price + price;
+        }
+        book.count = book.count + 1;
+        Ok(())";
+        assert!(rs.contains(body));
+    }
+
+    #[test]
+    fn zero_copy_guard_held_by_loop_bound_rejects_cpis() {
+        let err = try_transpile_program(
+            r#"deposit(payer: Signer, book: Book, price: u64): Result {
+                book.derive(["book", payer.key]);
+                for (let i = 0; i < book.count; i++) {
+                  SystemProgram.transfer(payer, book, price);
+                }
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("the fields of zero-copy account book can't be used in the statement or block that passes book to a CPI"));
+    }
+
+    #[test]
+    fn realloc_with_size_argument() {
+        let rs = transpile_program(
+            r#"grow(admin: Signer, vault: Vault, newSize: u64): Result {
+                vault.derive(["vault", admin.key]).realloc(newSize, admin);
+            }"#,
+        );
+        assert!(rs.contains(
+            "    #[account(
+        mut,
+        realloc = new_size as usize,
+        realloc::payer = admin,
+        realloc::zero = false,
+        seeds = [b\"vault\", admin.key().as_ref()],
+        bump,
+    )]"
+        ));
+        assert!(rs.contains("#[instruction(new_size: u64)]"));
+    }
+
+    #[test]
+    fn realloc_without_size_is_rejected() {
+        let err = try_transpile_program(
+            r#"grow(admin: Signer, vault: Vault): Result {
+                vault.derive(["vault", admin.key]).realloc(admin);
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Pass the new size and payer account arguments for realloc of vault"));
+    }
+
+    #[test]
+    fn address_constraints_from_accounts_and_arguments() {
+        let rs = transpile_program(
+            r#"withdraw(admin: Signer, vault: Vault, treasury: SystemAccount, authority: Pubkey): Result {
+                vault.address(treasury);
+                treasury.address(authority);
+                admin.address(vault.admin, VaultError.Locked);
+            }"#,
+        );
+        assert!(rs.contains("    #[account(address = treasury.key())]\n    pub vault: Account<'info, Vault>,"));
+        assert!(rs.contains("    #[account(mut, address = authority)]\n    pub treasury: SystemAccount<'info>,"));
+        assert!(rs.contains("    #[account(mut, address = vault.admin @ VaultError::Locked)]\n    pub admin: Signer<'info>,"));
+        assert!(rs.contains("#[instruction(authority: Pubkey)]"));
+    }
+
+    #[test]
+    fn custom_errors_on_has_one_and_close() {
+        let rs = transpile_program(
+            r#"close(admin: Signer, vault: Vault): Result {
+                vault.derive(["vault", admin.key]).has([admin], VaultError.Locked).close(admin, VaultError.Empty);
+            }"#,
+        );
+        assert!(rs.contains("        has_one = admin @ VaultError::Locked,\n"));
+        assert!(rs.contains("        close = admin,\n"));
+        assert!(rs.contains("        constraint = vault.key() != admin.key() @ VaultError::Empty,\n"));
+    }
+
+    #[test]
+    fn token_interface_accounts_and_transfer_checked() {
+        let rs = transpile_program(
+            r#"deposit(owner: Signer, mint: Mint, vault: TokenAccount, auth: UncheckedAccount, tokenProgram: TokenInterface, amount: u64, decimals: u8): Result {
+                vault.derive(["vault", owner.key], mint, auth.key).initIfNeeded(owner);
+                TokenProgram2022.transferChecked(vault, mint, vault, owner, amount, decimals);
+            }"#,
+        );
+        assert!(rs.contains("use anchor_spl::token_interface::{"));
+        assert!(rs.contains("        token::token_program = token_program,\n"));
+        assert!(rs.contains("    pub vault: InterfaceAccount<'info, TokenAccount>,"));
+        assert!(rs.contains("    pub mint: InterfaceAccount<'info, Mint>,"));
+        assert!(rs.contains("    pub token_program: Interface<'info, TokenInterface>,"));
+        assert!(rs.contains("transfer_checked(cpi_ctx, amount, decimals)?;"));
+    }
+
+    #[test]
+    fn token_interface_misuse_is_an_error() {
+        let err = try_transpile_program(
+            r#"deposit(owner: Signer, mint: Mint, vault: TokenAccount, amount: u64, decimals: u8): Result {
+                TokenProgram2022.transferChecked(vault, mint, vault, owner, amount, decimals);
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Add a `tokenProgram: TokenInterface` parameter to deposit"));
+        let err = try_transpile_program(
+            r#"deposit(owner: Signer, vault: TokenAccount, to: TokenAccount, tokenProgram: TokenInterface, amount: u64): Result {
+                TokenProgram.transfer(vault, to, owner, amount);
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Token-2022 mints need TokenProgram.transferChecked"));
+        let err = try_transpile_program(
+            r#"deposit(owner: Signer, program: TokenInterface): Result {}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Name the TokenInterface parameter tokenProgram"));
+    }
+
+    #[test]
+    fn mint_extensions() {
+        let rs = transpile_program(
+            r#"createMint(owner: Signer, mint: Mint, tokenProgram: TokenInterface): Result {
+                mint.derive(["mint"], owner.key, 6).init(owner).metadataPointer(owner, mint).closeAuthority(owner);
+            }"#,
+        );
+        assert!(rs.contains("        extensions::metadata_pointer::authority = owner,\n"));
+        assert!(rs.contains("        extensions::metadata_pointer::metadata_address = mint,\n"));
+        assert!(rs.contains("        extensions::close_authority::authority = owner,\n"));
+    }
+
+    #[test]
+    fn unsupported_mint_extensions_are_errors() {
+        let err = try_transpile_program(
+            r#"createMint(owner: Signer, mint: Mint, tokenProgram: TokenInterface): Result {
+                mint.derive(["mint"], owner.key, 6).init(owner).nonTransferable();
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("The nonTransferable mint extension is not supported"));
+        let err = try_transpile_program(
+            r#"createMint(owner: Signer, mint: Mint): Result {
+                mint.derive(["mint"], owner.key, 6).init(owner).closeAuthority(owner);
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("closeAuthority is a Token-2022 mint extension"));
+    }
+
+    #[test]
+    fn metadata_cpis() {
+        let rs = transpile_program(
+            r#"createToken(payer: Signer, mint: Mint, auth: UncheckedAccount, metadata: UncheckedAccount, name: Str<32>, uri: Str<200>, fee: u16): Result {
+                auth.derive(["auth"]);
+                MetadataProgram.createMetadataAccountsV3(metadata, mint, auth, payer, auth, name, "TKN", uri, fee, ["auth", auth.getBump()]);
+            }
+            rename(updateAuthority: Signer, metadata: MetadataAccount, name: Str<32>, uri: Str<200>): Result {
+                MetadataProgram.updateMetadataAccountsV2(metadata, updateAuthority, name, "TKN", uri, 500);
+            }"#,
+        );
+        assert!(rs.contains("let signer_seeds: &[&[&[u8]]; 1] = &[&[b\"auth\", &[ctx.bumps.auth]]];"));
+        assert!(rs.contains("            CreateMetadataAccountsV3 {\n                metadata: ctx.accounts.metadata.to_account_info(),"));
+        assert!(rs.contains("                seller_fee_basis_points: fee,\n"));
+        assert!(rs.contains("update_metadata_accounts_v2("));
+        assert!(rs.contains("    #[account(mut)]\n    pub metadata: Account<'info, MetadataAccount>,"));
+        assert!(rs.contains("    pub metadata_program: Program<'info, Metadata>,"));
+        assert!(rs.contains("    pub rent: Sysvar<'info, Rent>,"));
+
+        let err = try_transpile_program(
+            r#"burn(owner: Signer, metadata: MetadataAccount): Result {
+                MetadataProgram.burnNft(metadata, owner);
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "MetadataProgram.burnNft is not supported");
+    }
+
+    #[test]
+    fn system_program_cpis() {
+        let rs = transpile_program(
+            r#"make(payer: Signer, vault: SystemAccount, other: Signer, space: u64, lamports: u64): Result {
+                vault.derive(["vault", payer.key]);
+                SystemProgram.createAccount(payer, vault, lamports, space, TestProgram.PROGRAM_ID, ["vault", payer.key, vault.getBump()]);
+                SystemProgram.createAccount(payer, other, 1000000, 165, new Pubkey("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"));
+                SystemProgram.allocate(other, 200);
+                SystemProgram.assign(other, TestProgram.PROGRAM_ID);
+            }"#,
+        );
+        assert!(rs.contains("create_account(cpi_ctx, lamports, space, &crate::ID)?;"));
+        assert!(rs.contains("            &pubkey!(\"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA\"),\n        )?;"));
+        assert!(rs.contains("account_to_allocate: ctx.accounts.other.to_account_info(),"));
+        assert!(rs.contains("allocate(cpi_ctx, 200)?;"));
+        assert!(rs.contains("assign(cpi_ctx, &crate::ID)?;"));
+    }
+
+    #[test]
+    fn seeded_system_program_cpis() {
+        let rs = transpile_program(
+            r#"seeded(payer: Signer, vault: UncheckedAccount, base: Signer, seed: Str<32>, lamports: u64): Result {
+                SystemProgram.createAccountWithSeed(payer, vault, base, "vault", lamports, 165, TestProgram.PROGRAM_ID);
+                SystemProgram.transferWithSeed(vault, base, payer, seed, TestProgram.PROGRAM_ID, lamports);
+            }"#,
+        );
+        assert!(rs.contains("            CreateAccountWithSeed {\n                from: ctx.accounts.payer.to_account_info(),\n                to: ctx.accounts.vault.to_account_info(),\n                base: ctx.accounts.base.to_account_info(),\n            },"));
+        assert!(rs.contains("create_account_with_seed(cpi_ctx, \"vault\", lamports, 165, &crate::ID)?;"));
+        assert!(rs.contains("            TransferWithSeed {\n                from: ctx.accounts.vault.to_account_info(),\n                base: ctx.accounts.base.to_account_info(),\n                to: ctx.accounts.payer.to_account_info(),\n            },"));
+        assert!(rs.contains("transfer_with_seed(cpi_ctx, seed.to_string(), &crate::ID, lamports)?;"));
+        assert!(rs.contains("    #[account(mut)]\n    pub base: Signer<'info>,"));
+
+        let err = try_transpile_program(
+            r#"close(payer: Signer, vault: SystemAccount): Result {
+                SystemProgram.closeAccount(vault, payer);
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "SystemProgram.closeAccount is not supported");
+    }
+
+    #[test]
+    fn cpi_into_declared_anchor_program() {
+        let rs = transpile_source(
+            r#"declare class CounterProgram {
+              increment(counter: UncheckedAccount, authority: Signer, amount: u64): Result;
+            }
+            export default class CallerProgram {
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+              bump(user: Signer, counter: UncheckedAccount, auth: UncheckedAccount, amount: u64): Result {
+                counter.mut();
+                auth.derive(["auth"]);
+                CounterProgram.increment(counter, auth, amount, ["auth", auth.getBump()]);
+              }
+            }"#,
+        )
+        .unwrap();
+        assert!(rs.contains("            ctx.accounts.counter_program.to_account_info(),\n            counter_program::cpi::accounts::Increment {"));
+        assert!(rs.contains("                authority: ctx.accounts.auth.to_account_info(),\n"));
+        assert!(rs.contains("            signer_seeds,\n"));
+        assert!(rs.contains("counter_program::cpi::increment(cpi_ctx, amount)?;"));
+        assert!(rs.contains("    pub counter_program: Program<'info, counter_program::program::CounterProgram>,"));
+    }
+
+    #[test]
+    fn undeclared_external_methods_are_errors() {
+        let err = transpile_source(
+            r#"declare class CounterProgram {
+              increment(counter: UncheckedAccount, amount: u64): Result;
+            }
+            export default class CallerProgram {
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+              bump(user: Signer, counter: UncheckedAccount, amount: u64): Result {
+                CounterProgram.decrement(counter, amount);
+              }
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "CounterProgram.decrement is not declared");
+
+        let err = transpile_source(
+            r#"class CounterProgram {
+              increment(counter: UncheckedAccount, amount: u64): Result;
+            }
+            export default class CallerProgram {
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "External program CounterProgram must be declared with `declare class`");
+    }
+
+    #[test]
+    fn raw_invoke_with_pubkey_literals_and_flag_arguments() {
+        let rs = transpile_program(
+            r#"poke(user: Signer, target: UncheckedAccount, auth: UncheckedAccount, data: Uint8Array, writable: Boolean): Result {
+                auth.derive(["auth"]);
+                const memo = new Instruction(new Pubkey("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo"), [
+                  { pubkey: new Pubkey("SysvarC1ock11111111111111111111111111111111"), isSigner: false, isWritable: false },
+                  { pubkey: target.key, isSigner: false, isWritable: writable },
+                ], data);
+                invoke(memo, [target]);
+                invokeSigned(new Instruction(target.key, [
+                  { pubkey: auth.key, isSigner: true, isWritable: false },
+                ], [1, 2, 255]), [auth], ["auth", auth.getBump()]);
+            }"#,
+        );
+        assert!(rs.contains("program_id: pubkey!(\"Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo\"),"));
+        assert!(rs.contains("AccountMeta::new_readonly(\n                    pubkey!(\"SysvarC1ock11111111111111111111111111111111\"),\n                    false,\n                ),"));
+        assert!(!rs.contains("pub mod memo"));
+        assert!(rs.contains(
+            "AccountMeta {\n                    pubkey: ctx.accounts.target.key(),\n                    is_signer: false,\n                    is_writable: writable,\n                },"
+        ));
+        assert!(rs.contains("AccountMeta::new_readonly(ctx.accounts.auth.key(), true)"));
+        assert!(rs.contains("    #[account(mut)]\n    /// CHECK: This acc is safe\n    pub target: UncheckedAccount<'info>,"));
+    }
+
+    #[test]
+    fn remaining_accounts_are_looked_up_with_a_checked_index() {
+        let rs = transpile_program(
+            r#"pay(payer: Signer, vault: Vault, index: u64, amount: u64): Result {
+                SystemProgram.transfer(payer, Context.remainingAccounts[index], amount);
+                vault.admin = Context.remainingAccounts[0].key;
+                vault.amount = Context.remainingAccounts.length;
+            }"#,
+        );
+        assert!(rs.contains("ctx: Context<'_, '_, '_, 'info, PayContext<'info>>,"));
+        assert!(rs.contains(
+            "            to: ctx\n                .remaining_accounts\n                .get(index as usize)\n                .ok_or(ErrorCode::AccountNotEnoughKeys)?\n                .to_account_info(),\n"
+        ));
+        assert!(rs.contains(".get(0)\n            .ok_or(ErrorCode::AccountNotEnoughKeys)?\n            .key();"));
+        assert!(rs.contains("ctx.accounts.vault.amount = ctx.remaining_accounts.len() as u64;"));
+        assert!(!rs.contains("remaining_accounts["));
+    }
+
+    #[test]
+    fn bounded_loops_become_rust_for_loops() {
+        let rs = transpile_program(
+            r#"pay(payer: Signer, vault: Vault, amount: u64, n: u64): Result {
+                for (const payee of Context.remainingAccounts) {
+                  SystemProgram.transfer(payer, payee, amount);
+                  vault.admin = payee.key;
+                }
+                for (let i = 1; i <= n; i += 1) {
+                  vault.amount = vault.amount + i;
+                }
+            }"#,
+        );
+        assert!(rs.contains("        for payee in ctx.remaining_accounts.iter() {\n"));
+        assert!(rs.contains("                to: payee.to_account_info(),\n"));
+        assert!(rs.contains("            ctx.accounts.vault.admin = payee.key();\n"));
+        assert!(rs.contains("        for i in 1..=n {\n"));
+    }
+
+    #[test]
+    fn break_and_continue_in_loops() {
+        let rs = transpile_program(
+            r#"pay(payer: Signer, vault: Vault, amount: u64): Result {
+                for (const payee of Context.remainingAccounts) {
+                  if (vault.locked) {
+                    break;
+                  }
+                  if (payee.key.equals(payer.key)) {
+                    continue;
+                  }
+                  SystemProgram.transfer(payer, payee, amount);
+                }
+            }"#,
+        );
+        assert!(rs.contains("            if ctx.accounts.vault.locked {\n                break;\n            }\n"));
+        assert!(rs.contains("            if payee.key() == ctx.accounts.payer.key() {\n                continue;\n            }\n"));
+    }
+
+    #[test]
+    fn break_outside_a_loop_is_rejected() {
+        let err = try_transpile_program(
+            r#"close(admin: Signer, vault: Vault): Result {
+                if (vault.locked) {
+                  break;
+                }
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "break and continue can only be used inside a for loop");
+    }
+
+    #[test]
+    fn while_loops_are_rejected() {
+        let err = try_transpile_program(
+            r#"drain(admin: Signer, vault: Vault): Result {
+                while (vault.amount > 0) {
+                  vault.amount = vault.amount - 1;
+                }
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("while loops are not supported"));
+    }
+
+    #[test]
+    fn if_on_an_optional_account_binds_it_for_the_body() {
+        let rs = transpile_program(
+            r#"pay(payer: Signer, vault: Vault, amount: u64, referrer?: SystemAccount, tip?: u64): Result {
+                if (referrer) {
+                  SystemProgram.transfer(payer, referrer, amount);
+                  vault.admin = referrer.key;
+                } else {
+                  vault.admin = payer.key;
+                }
+                if (tip) {
+                  vault.amount = tip;
+                }
+            }"#,
+        );
+        assert!(rs.contains("        if let Some(referrer) = ctx.accounts.referrer.as_mut() {\n"));
+        assert!(rs.contains("                to: referrer.to_account_info(),\n"));
+        assert!(rs.contains("            ctx.accounts.vault.admin = referrer.key();\n        } else {\n"));
+        assert!(rs.contains("        if let Some(tip) = tip {\n            ctx.accounts.vault.amount = tip;\n"));
+        assert!(rs.contains("    pub referrer: Option<SystemAccount<'info>>,"));
+        assert!(rs.contains("amount: u64, tip: Option<u64>) -> Result<()> {"));
+    }
+
+    #[test]
+    fn optional_account_used_outside_its_if_is_rejected() {
+        let err = try_transpile_program(
+            r#"pay(payer: Signer, vault: Vault, amount: u64, referrer?: SystemAccount): Result {
+                if (referrer) {
+                  vault.admin = referrer.key;
+                } else {
+                  SystemProgram.transfer(payer, referrer, amount);
+                }
+            }"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("optional account referrer can only be used inside `if (referrer) { ... }`"));
+    }
+
+    #[test]
+    fn optional_argument_used_outside_its_if_is_rejected() {
+        let err = try_transpile_program(
+            r#"pay(payer: Signer, vault: Vault, tip?: u64): Result {
+                vault.amount = tip;
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "could not compile due to 1 type error(s)");
+    }
+
+    #[test]
+    fn if_with_a_boolean_test() {
+        let rs = transpile_program(
+            r#"bid(admin: Signer, vault: Vault, price: u64): Result {
+                if (price > 10 && !vault.locked) {
+                  vault.amount = price;
+                } else {
+                  vault.locked = true;
+                }
+                if (vault.admin === admin.key) {
+                  vault.amount = 0;
+                }
+            }"#,
+        );
+        assert!(rs.contains(
+            "        if price > 10 && !ctx.accounts.vault.locked {\n            ctx.accounts.vault.amount = price;\n        } else {\n            ctx.accounts.vault.locked = true;\n        }\n"
+        ));
+        assert!(rs.contains("        if ctx.accounts.vault.admin == ctx.accounts.admin.key() {\n"));
+    }
+
+    const ORDER_PROGRAM: &str = r#"import { Account, Pubkey, Signer, SystemAccount, SystemProgram, Str, u64, u8 } from "@solanaturbine/poseidon";
+        export default class Orders {
+          static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+          createOrder(maker: Signer, order: Order, taker: SystemAccount, params: CreateOrderParams) {
+            order.derive(["order", maker.key, params.id.toBytes(), params.market]).init(maker);
+            order.price = params.price;
+            order.label = params.label;
+            SystemProgram.transfer(maker, taker, params.price);
+          }
+        }
+        export interface Order extends Account {
+          price: u64;
+          label: Str<16>;
+        }
+    "#;
+
+    #[test]
+    fn struct_arguments() {
+        let rs = transpile_source(&format!(
+            "{}\nexport interface CreateOrderParams {{ id: u64; price: u64; market: Pubkey; label: Str<16> }}",
+            ORDER_PROGRAM
+        ))
+        .unwrap();
+        assert!(rs.contains("        params: CreateOrderParams,\n    ) -> Result<()> {"));
+        assert!(rs.contains("        ctx.accounts.order.label = params.label.clone();\n"));
+        assert!(rs.contains("        transfer(cpi_ctx, params.price)?;\n"));
+        assert!(rs.contains(
+            "        seeds = [\n            b\"order\",\n            maker.key().as_ref(),\n            params.id.to_le_bytes().as_ref(),\n            params.market.as_ref(),\n        ],\n"
+        ));
+        assert!(rs.contains("#[instruction(params: CreateOrderParams)]"));
+        assert!(rs.contains(
+            "#[derive(AnchorSerialize, AnchorDeserialize, Clone)]\npub struct CreateOrderParams {\n    pub id: u64,\n    pub price: u64,\n    pub market: Pubkey,\n    pub label: String,\n}"
+        ));
+    }
+
+    #[test]
+    fn unknown_struct_argument_fields_are_errors() {
+        let program = ORDER_PROGRAM.replace("order.price = params.price;", "order.price = params.prise;");
+        let err = transpile_source(&format!(
+            "{}\nexport interface CreateOrderParams {{ id: u64; price: u64; market: Pubkey; label: Str<16> }}",
+            program
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "could not compile due to 1 type error(s)");
+
+        let program = ORDER_PROGRAM.replace("params.market]", "params.markt]");
+        let err = transpile_source(&format!(
+            "{}\nexport interface CreateOrderParams {{ id: u64; price: u64; market: Pubkey; label: Str<16> }}",
+            program
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "could not compile due to 1 type error(s)");
+    }
+
+    #[test]
+    fn struct_with_an_unsupported_field_is_rejected() {
+        let err = transpile_source(&format!(
+            "{}\nexport interface CreateOrderParams {{ id: u64; price: u64; market: Pubkey; label: Str<16>; order: Order }}",
+            ORDER_PROGRAM
+        ))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "unsupported type Order for CreateOrderParams.order");
+    }
+
+    #[test]
+    fn program_constants() {
+        let rs = transpile_program(
+            r#"static readonly LIMIT = 10;
+            static FEE_BPS: u32 = 30;
+            static readonly OFFSET = -5;
+            static readonly SEED = "vault";
+            deposit(admin: Signer, vault: Vault, amount: u64): Result {
+                vault.derive([TestProgram.SEED, admin.key]);
+                vault.constraint(amount.lte(TestProgram.LIMIT));
+                vault.amount = amount + TestProgram.LIMIT;
+            }"#,
+        );
+        assert!(rs.contains("#[constant]\npub const LIMIT: u64 = 10;\n"));
+        assert!(rs.contains("#[constant]\npub const FEE_BPS: u32 = 30;\n"));
+        assert!(rs.contains("#[constant]\npub const OFFSET: i64 = -5;\n"));
+        assert!(rs.contains("#[constant]\npub const SEED: &str = \"vault\";\n"));
+        assert!(rs.contains("seeds = [SEED.as_bytes(), admin.key().as_ref()],"));
+        assert!(rs.contains("constraint = amount <= LIMIT,"));
+        assert!(rs.contains("ctx.accounts.vault.amount = amount + LIMIT;"));
+    }
+
+    #[test]
+    fn invalid_program_constants_are_rejected() {
+        let err = |constant: &str| {
+            try_transpile_program(&format!("{}\nclose(admin: Signer): Result {{}}", constant))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(err("static readonly ID = 1;"), "Constant ID clashes with the program ID, rename it");
+        assert_eq!(err("static readonly LIMIT;"), "Constant LIMIT must have a value");
+        assert_eq!(err("static readonly LIMIT = [1, 2];"), "Constant LIMIT must be an integer, string, boolean or pubkey");
+        assert_eq!(err("static readonly OWNER = new Pubkey(1);"), "Constant OWNER must be new Pubkey(\"...\")");
+        assert_eq!(err("static readonly LIMIT: Vault = 1;"), "unsupported type Vault for constant LIMIT");
+    }
+
+    #[test]
+    fn includes_on_a_vec_of_strings() {
+        let rs = transpile_source(
+            r#"import { Account, Boolean, Pubkey, Signer, Str, Vec } from "@solanaturbine/poseidon";
+            export default class Clubs {
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+              check(owner: Signer, club: Club, tag: Str<8>) {
+                club.plays = club.tags.includes("chess");
+                club.tagged = club.tags.includes(tag);
+              }
+            }
+            export interface Club extends Account {
+              tags: Vec<Str<8>, 4>
+              plays: Boolean
+              tagged: Boolean
+            }"#,
+        )
+        .unwrap();
+        assert!(rs.contains("ctx.accounts.club.tags.iter().any(|s| s.as_str() == \"chess\");"));
+        assert!(rs.contains("ctx.accounts.club.tags.iter().any(|s| s.as_str() == tag);"));
+        assert!(!rs.contains(".contains(&"));
+    }
+
+    #[test]
+    fn pubkey_literals_find_program_address_and_key_comparisons() {
+        let rs = transpile_program(
+            r#"static readonly ADMIN = new Pubkey("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
+            update(admin: Signer, vault: Vault, id: u64): Result {
+                vault.constraint(admin.key.equals(TestProgram.ADMIN) && !vault.admin.equals(new Pubkey("So11111111111111111111111111111111111111112")));
+                vault.admin = new Pubkey("4Nd1mBQtrMJVYVfKf2PJy9NZUZdTAsp7D4xWLs4gDB4T");
+                const [pda, bump] = Pubkey.findProgramAddress(["vault", admin.key, id.toBytes()], TestProgram.PROGRAM_ID);
+                vault.admin = pda;
+                vault.bump = bump;
+                vault.locked = !admin.key.equals(vault.admin);
+            }"#,
+        );
+        assert!(rs.contains("#[constant]\npub const ADMIN: Pubkey = pubkey!(\"9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin\");\n"));
+        assert!(!rs.contains("pub mod admin"));
+        assert!(rs.contains("        constraint = admin.key() == ADMIN\n            && !(vault.admin == pubkey!(\"So11111111111111111111111111111111111111112\")),\n"));
+        assert!(rs.contains(".admin = pubkey!(\"4Nd1mBQtrMJVYVfKf2PJy9NZUZdTAsp7D4xWLs4gDB4T\");\n"));
+        assert!(!rs.contains("declare_id!(\"So11"));
+        assert!(rs.contains(
+            "        let (pda, bump) = Pubkey::find_program_address(\n            &[b\"vault\", ctx.accounts.admin.key().as_ref(), id.to_le_bytes().as_ref()],\n            &crate::ID,\n        );\n"
+        ));
+        assert!(rs.contains("        ctx.accounts.vault.admin = pda;\n        ctx.accounts.vault.bump = bump;\n"));
+        assert!(rs.contains("!(ctx.accounts.admin.key() == ctx.accounts.vault.admin);"));
+    }
+
+    #[test]
+    fn lamport_helpers() {
+        let rs = transpile_program(
+            r#"withdraw(admin: Signer, vault: Vault, recipient: SystemAccount, amount: u64): Result {
+                vault.subLamports(amount + 5000);
+                recipient.addLamports(amount);
+                vault.amount = vault.lamports;
+            }"#,
+        );
+        assert!(rs.contains("        ctx.accounts.vault.sub_lamports(amount + 5000)?;\n"));
+        assert!(rs.contains("        ctx.accounts.recipient.add_lamports(amount)?;\n"));
+        assert!(rs.contains("        ctx.accounts.vault.amount = ctx.accounts.vault.get_lamports();\n"));
+        assert!(rs.contains("    #[account(mut)]\n    pub vault: Account<'info, Vault>,"));
+        assert!(rs.contains("    #[account(mut)]\n    pub recipient: SystemAccount<'info>,"));
+    }
+
+    #[test]
+    fn lamport_helpers_need_an_amount() {
+        let err = try_transpile_program(
+            r#"withdraw(admin: Signer, vault: Vault): Result {
+                vault.subLamports();
+            }"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "vault.subLamports expects an amount");
+    }
+}
@@ -3,10 +3,15 @@ use core::panic;
 use proc_macro2::{Ident, Literal, TokenStream};
 use quote::quote;
 use std::collections::HashMap;
+use swc_common::Span;
 use swc_ecma_ast::{ClassExpr, Lit};
 
+use crate::rs_types::external_program::ExternalProgram;
 use crate::rs_types::program_account::ProgramAccount;
+use crate::rs_types::program_constant::ProgramConstant;
+use crate::rs_types::program_error::ProgramError;
 use crate::rs_types::program_instruction::ProgramInstruction;
+use crate::rs_types::program_struct::ProgramStruct;
 use anyhow::{anyhow, Ok, Result};
 type SubMember = HashMap<String, Option<String>>; // submember_name : alias
 type Member = HashMap<String, SubMember>; // member_name : submembers
@@ -17,7 +22,13 @@ pub struct ProgramModule {
     pub custom_types: HashMap<String, ProgramAccount>,
     pub instructions: Vec<ProgramInstruction>,
     pub accounts: Vec<ProgramAccount>,
+    pub structs: Vec<ProgramStruct>,
+    pub errors: Vec<ProgramError>,
+    pub constants: Vec<ProgramConstant>,
+    pub casts: HashMap<Span, String>, // span of a cast call : type of its value
+    pub external_programs: Vec<ExternalProgram>,
     pub imports: ProgramImport,
+    pub init_space: bool,
 }
 
 impl ProgramModule {
@@ -28,7 +39,13 @@ impl ProgramModule {
             custom_types: HashMap::new(),
             instructions: vec![],
             accounts: vec![],
+            structs: vec![],
+            errors: vec![],
+            constants: vec![],
+            casts: HashMap::new(),
+            external_programs: vec![],
             imports: HashMap::new(),
+            init_space: false,
         }
     }
     pub fn add_import(&mut self, src_pkg: &str, member_name: &str, sub_member_name: &str) {
@@ -76,7 +93,14 @@ impl ProgramModule {
             .expect("Expected program to have a valid name")
             .to_string();
         let class_members = &class.class.body;
-        let _ = class_members
+        // constants are collected first, so every instruction can use them
+        self.constants = class_members
+            .iter()
+            .filter_map(|c| c.as_class_prop())
+            .filter(|c| c.is_static && c.key.as_ident().is_some_and(|k| k.sym != "PROGRAM_ID"))
+            .map(ProgramConstant::from_class_prop)
+            .collect::<Result<Vec<ProgramConstant>>>()?;
+        class_members
             .iter()
             .map(|c| {
                 match c.as_class_prop() {
@@ -102,7 +126,7 @@ impl ProgramModule {
                                 Lit::Str(s) => s.value.to_string(),
                                 _ => panic!("Invalid program ID"),
                             };
-                        } else {
+                        } else if !c.is_static {
                             panic!("Invalid declaration")
                         }
                     }
@@ -118,7 +142,7 @@ impl ProgramModule {
                 }
                 Ok(())
             })
-            .collect::<Result<Vec<()>>>();
+            .collect::<Result<Vec<()>>>()?;
         Ok(())
     }
 
@@ -130,11 +154,6 @@ impl ProgramModule {
         let program_id = Literal::string(&self.id);
         let serialized_instructions: Vec<TokenStream> =
             self.instructions.iter().map(|x| x.to_tokens()).collect();
-        let serialized_account_structs: Vec<TokenStream> = self
-            .instructions
-            .iter()
-            .map(|x| x.accounts_to_tokens())
-            .collect();
 
         let imports: TokenStream = match !self.imports.is_empty() {
             true => {
@@ -173,24 +192,46 @@ impl ProgramModule {
                 quote!()
             }
         };
-        let serialized_accounts: Vec<TokenStream> =
-            self.accounts.iter().map(|x| x.to_tokens()).collect();
+        let constants: Vec<TokenStream> = self.constants.iter().map(|x| x.to_tokens()).collect();
+        let external_programs: Vec<TokenStream> = self
+            .external_programs
+            .iter()
+            .map(|x| x.to_tokens())
+            .collect();
         let program = quote! {
             use anchor_lang::prelude::*;
             #imports
             declare_id!(#program_id);
 
+            #(#constants)*
+
+            #(#external_programs)*
+
             #[program]
             pub mod #program_name {
                 use super::*;
 
                 #(#serialized_instructions)*
             }
-
-            #(#serialized_account_structs)*
-
-            #(#serialized_accounts)*
         };
         Ok(program)
     }
+
+    /// Account, struct and error definitions that follow the instructions' `Accounts` structs.
+    pub fn items_to_tokens(&self) -> Result<TokenStream> {
+        let serialized_accounts: Vec<TokenStream> = self
+            .accounts
+            .iter()
+            .map(|x| x.to_tokens(self.init_space))
+            .collect::<Result<Vec<TokenStream>>>()?;
+        let serialized_structs: Vec<TokenStream> = self.structs.iter().map(|x| x.to_tokens()).collect();
+        let serialized_errors: Vec<TokenStream> = self.errors.iter().map(|x| x.to_tokens()).collect();
+        Ok(quote! {
+            #(#serialized_accounts)*
+
+            #(#serialized_structs)*
+
+            #(#serialized_errors)*
+        })
+    }
 }
@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Result};
+use convert_case::{Case, Casing};
+use core::panic;
+use proc_macro2::{Ident, TokenStream};
+use quote::quote;
+use swc_ecma_ast::TsInterfaceDecl;
+
+use crate::helpers::extract_type::{extract_max_len, extract_ts_type};
+use crate::rs_types::program_account::ProgramAccountField;
+use crate::ts_types::rs_type_from_str;
+
+/// An exported interface that does not extend `Account`. It is emitted as a Borsh struct so
+/// instructions can take it as an argument, e.g. `params: CreateOrderParams`.
+#[derive(Debug, Clone)]
+pub struct ProgramStruct {
+    pub name: String,
+    pub fields: Vec<ProgramAccountField>,
+}
+
+impl ProgramStruct {
+    pub fn from_ts_expr(interface: TsInterfaceDecl) -> Result<Self> {
+        let name: String = interface.id.sym.to_string();
+        let fields = interface
+            .body
+            .body
+            .iter()
+            .map(|f| {
+                let field = f
+                    .clone()
+                    .ts_property_signature()
+                    .ok_or(anyhow!("{} can only have property fields", name))?;
+                let field_name = field
+                    .key
+                    .ident()
+                    .ok_or(anyhow!(
+                        "the fields of {} must be named by identifiers",
+                        name
+                    ))?
+                    .sym
+                    .to_string();
+                let binding = field.type_ann.ok_or(anyhow!(
+                    "{}.{} needs a type annotation",
+                    name,
+                    field_name
+                ))?;
+                let max_len = extract_max_len(&binding)
+                    .map_err(|_| anyhow!("invalid length for {}.{}", name, field_name))?;
+                let (field_type, _len, _optional) = extract_ts_type(binding)
+                    .map_err(|_| anyhow!("unsupported type for {}.{}", name, field_name))?;
+                rs_type_from_str(&field_type).map_err(|_| {
+                    anyhow!(
+                        "unsupported type {} for {}.{}",
+                        field_type,
+                        name,
+                        field_name
+                    )
+                })?;
+                Ok(ProgramAccountField {
+                    name: field_name,
+                    of_type: field_type.to_string(),
+                    max_len,
+                })
+            })
+            .collect::<Result<Vec<ProgramAccountField>>>()?;
+        Ok(Self { name, fields })
+    }
+
+    pub fn get_field(&self, name: &str) -> Option<&ProgramAccountField> {
+        self.fields
+            .iter()
+            .find(|f| f.name.to_case(Case::Snake) == name)
+    }
+
+    pub fn to_tokens(&self) -> TokenStream {
+        let struct_name = Ident::new(&self.name, proc_macro2::Span::call_site());
+        let fields: Vec<TokenStream> = self
+            .fields
+            .iter()
+            .map(|field| {
+                let field_name = Ident::new(
+                    &field.name.to_case(Case::Snake),
+                    proc_macro2::Span::call_site(),
+                );
+                let field_type = rs_type_from_str(&field.of_type)
+                    .unwrap_or_else(|_| panic!("Invalid type: {}", field.of_type));
+                quote! { pub #field_name: #field_type }
+            })
+            .collect();
+        quote! {
+            #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+            pub struct #struct_name {
+                #(#fields),*
+            }
+        }
+    }
+}
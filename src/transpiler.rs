@@ -6,18 +6,38 @@ use std::{
 };
 
 use crate::{
-    helpers::format_account_struct::{extract_accounts_structs, reorder_struct, replace_struct},
-    rs_types::{ProgramAccount, ProgramModule},
+    helpers::{
+        extract_type::inline_constant_lengths,
+        format_account_struct::format_accounts_struct,
+    },
+    rs_types::{ExternalProgram, ProgramAccount, ProgramError, ProgramModule, ProgramStruct},
+    type_checker::TypeChecker,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use swc_common::{sync::Lrc, SourceMap};
 use swc_ecma_ast::*;
 
-pub fn transpile(module: &Module, output_file_name: &String) -> Result<()> {
+pub fn transpile(
+    module: &Module,
+    cm: &Lrc<SourceMap>,
+    output_file_name: &String,
+    init_space: bool,
+) -> Result<()> {
+    let formatted_program = transpile_to_string(module, cm, init_space)?;
+    fs::write(output_file_name, formatted_program)?;
+    Ok(())
+}
+
+/// Transpiles a parsed TypeScript program into the formatted source of an Anchor program.
+pub fn transpile_to_string(module: &Module, cm: &Lrc<SourceMap>, init_space: bool) -> Result<String> {
     let mut imports = vec![];
     let mut accounts: HashMap<String, ProgramAccount> = HashMap::new();
     let mut program_class: Option<ClassExpr> = None;
     let mut custom_types: HashMap<String, ProgramAccount> = HashMap::new();
     let mut program = ProgramModule::new();
+    program.init_space = init_space;
+    let mut module = module.clone();
+    inline_constant_lengths(&mut module);
     let mut stack: Vec<&ModuleItem> = module.body.iter().collect();
 
     while let Some(item) = stack.pop() {
@@ -43,14 +63,34 @@ pub fn transpile(module: &Module, output_file_name: &String) -> Result<()> {
             // Extract custom accounts
             ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(class_decl)) => {
                 match class_decl.clone().decl {
+                    // interfaces that don't extend an account type are plain argument structs
+                    Decl::TsInterface(interface) if interface.extends.is_empty() => {
+                        program
+                            .structs
+                            .push(ProgramStruct::from_ts_expr(*interface)?);
+                    }
                     Decl::TsInterface(interface) => {
                         let custom_account = ProgramAccount::from_ts_expr(*interface);
                         custom_types.insert(custom_account.name.clone(), custom_account.clone());
                         accounts.insert(custom_account.name.clone(), custom_account.clone());
                     }
+                    Decl::TsEnum(ts_enum) => {
+                        program.errors.push(ProgramError::from_ts_enum(*ts_enum));
+                    }
+                    Decl::Class(class_decl) => {
+                        program
+                            .external_programs
+                            .push(ExternalProgram::from_class_decl(class_decl)?);
+                    }
                     _ => panic!("Invalid export statement"),
                 }
             }
+            // Extract external programs
+            ModuleItem::Stmt(Stmt::Decl(Decl::Class(class_decl))) => {
+                program
+                    .external_programs
+                    .push(ExternalProgram::from_class_decl(class_decl.clone())?);
+            }
             _ => panic!("Invalid syntax, cannot match: {:?}", item),
         }
     }
@@ -60,25 +100,51 @@ pub fn transpile(module: &Module, output_file_name: &String) -> Result<()> {
 
     match program_class {
         Some(c) => {
+            let mut checker = TypeChecker::new(&c, &custom_types, &program.structs)?;
+            checker.check_class(&c);
+            if !checker.errors.is_empty() {
+                checker.report(cm);
+                return Err(anyhow!(
+                    "could not compile due to {} type error(s)",
+                    checker.errors.len()
+                ));
+            }
+            program.casts = checker.casts;
             program.populate_from_class_expr(&c, &custom_types)?;
         }
         None => panic!("Program class undefined"),
     }
-    let serialized_program = program.to_tokens()?.to_string();
-
-    let mut formatted_program = PrettyPlease::default().format_str(&serialized_program)?;
-
-    let extracted_account_struct = extract_accounts_structs(&formatted_program);
+    let mut formatted_program = PrettyPlease::default().format_str(program.to_tokens()?.to_string())?;
+    for ix in program.instructions.iter() {
+        formatted_program.push_str(&format_accounts_struct(ix.accounts_to_tokens())?);
+    }
+    formatted_program.push_str(&PrettyPlease::default().format_str(program.items_to_tokens()?.to_string())?);
+    Ok(formatted_program)
+}
 
-    for account_struct in extracted_account_struct {
-        let (header, reordered_account_struct) = reorder_struct(&account_struct)?;
+/// Parses TypeScript source, for tests.
+#[cfg(test)]
+pub fn parse_source(src: &str) -> Result<(Module, Lrc<SourceMap>)> {
+    use swc_common::{BytePos, FileName};
+    use swc_ecma_parser::{lexer::Lexer, Parser, StringInput, Syntax};
 
-        formatted_program = replace_struct(&formatted_program, &header, &reordered_account_struct);
-    }
+    let cm: Lrc<SourceMap> = Default::default();
+    let fm = cm.new_source_file(FileName::Anon, src.to_string());
+    let lexer = Lexer::new(
+        Syntax::Typescript(Default::default()),
+        Default::default(),
+        StringInput::new(&fm.src, BytePos(0), BytePos(fm.src.len() as u32)),
+        None,
+    );
+    let module = Parser::new_from(lexer)
+        .parse_typescript_module()
+        .map_err(|e| anyhow!("failed to parse module: {:?}", e.kind()))?;
+    Ok((module, cm))
+}
 
-    fs::write(
-        &output_file_name,
-        PrettyPlease::default().format_str(formatted_program)?,
-    )?;
-    Ok(())
+/// Transpiles TypeScript source, for tests.
+#[cfg(test)]
+pub fn transpile_source(src: &str) -> Result<String> {
+    let (module, cm) = parse_source(src)?;
+    transpile_to_string(&module, &cm, false)
 }
@@ -37,7 +37,7 @@ pub const STANDARD_ARRAY_TYPES: [&str; 13] = [
     "Vec<Boolean>",
 ];
 
-pub const STANDARD_ACCOUNT_TYPES: [&str; 7] = [
+pub const STANDARD_ACCOUNT_TYPES: [&str; 9] = [
     "Signer",
     "UncheckedAccount",
     "AccountInfo",
@@ -45,6 +45,8 @@ pub const STANDARD_ACCOUNT_TYPES: [&str; 7] = [
     "SystemAccount",
     "AssociatedTokenAccount",
     "Mint",
+    "TokenInterface",
+    "MetadataAccount",
 ];
 
 use crate::errors::PoseidonError;
@@ -84,3 +86,30 @@ pub fn rs_type_from_str(str: &str) -> Result<TokenStream, Error> {
         _ => Err(PoseidonError::InvalidType(str.to_string()))?,
     }
 }
+
+/// Borsh-serialized size of a field, using `max_len` (outermost first) for `Str`, `Vec` and `Array` types.
+pub fn space_from_str(str: &str, max_len: &[u32]) -> Result<u32, Error> {
+    let next_len = || max_len.first().copied().unwrap_or(0);
+    match str {
+        "u8" | "i8" | "Boolean" => Ok(1),
+        "u16" | "i16" => Ok(2),
+        "u32" | "i32" => Ok(4),
+        "u64" | "i64" | "usize" | "isize" => Ok(8),
+        "u128" | "i128" => Ok(16),
+        "Pubkey" => Ok(32),
+        "Str" => Ok(4 + next_len()),
+        "Uint8Array" => Ok(4 + next_len()),
+        _ => {
+            let elem_max_len = max_len.get(1..).unwrap_or(&[]);
+            if let Some(elem_type) = str.strip_prefix("Vec<").and_then(|s| s.strip_suffix('>')) {
+                Ok(4 + next_len() * space_from_str(elem_type, elem_max_len)?)
+            } else if let Some(elem_type) =
+                str.strip_prefix("Array<").and_then(|s| s.strip_suffix('>'))
+            {
+                Ok(next_len() * space_from_str(elem_type, elem_max_len)?)
+            } else {
+                Err(PoseidonError::InvalidType(str.to_string()))?
+            }
+        }
+    }
+}
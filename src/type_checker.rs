@@ -0,0 +1,1191 @@
+use anyhow::Result;
+use convert_case::{Case, Casing};
+use std::collections::HashMap;
+use swc_common::{
+    errors::{ColorConfig, Handler},
+    sync::Lrc,
+    SourceMap, Span, Spanned,
+};
+use swc_ecma_ast::*;
+
+use crate::helpers::extract_type::extract_ts_type;
+use crate::rs_types::program_constant::ProgramConstant;
+use crate::rs_types::{ProgramAccount, ProgramStruct};
+use crate::ts_types::STANDARD_ACCOUNT_TYPES;
+
+/// The type of an integer literal, which takes the type of the integer it is used with.
+const INTEGER: &str = "{integer}";
+
+/// The largest integer a JavaScript number holds exactly, `Number.MAX_SAFE_INTEGER`.
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+/// The integer type a cast method such as `amount.toU64()` converts to.
+pub fn cast_target(method: &str) -> Option<&'static str> {
+    Some(match method {
+        "toU8" => "u8",
+        "toI8" => "i8",
+        "toU16" => "u16",
+        "toI16" => "i16",
+        "toU32" => "u32",
+        "toI32" => "i32",
+        "toU64" => "u64",
+        "toI64" => "i64",
+        "toU128" => "u128",
+        "toI128" => "i128",
+        _ => return None,
+    })
+}
+
+/// The seeds and program id of a `Pubkey.findProgramAddress([...], programId)` call.
+pub fn find_program_address_call(expr: &Expr) -> Option<(&[Option<ExprOrSpread>], &Expr)> {
+    let call = expr.as_call()?;
+    let callee = call.callee.as_expr()?.as_member()?;
+    let (obj, prop) = (callee.obj.as_ident()?, callee.prop.as_ident()?);
+    if obj.sym != "Pubkey" || prop.sym != "findProgramAddress" {
+        return None;
+    }
+    let seeds = call.args.first()?.expr.as_array()?;
+    let program_id = call.args.get(1)?.expr.as_ref();
+    Some((&seeds.elems, program_id))
+}
+
+/// The value and target type of a cast call, e.g. `(acc.bump, "u64")` for `acc.bump.toU64()`.
+/// Parentheses around the value are dropped, since the cast is lowered to a function call.
+pub fn cast_call(expr: &Expr) -> Option<(&Expr, &'static str)> {
+    let callee = expr.as_call()?.callee.as_expr()?.as_member()?;
+    let target = cast_target(callee.prop.as_ident()?.sym.as_ref())?;
+    let mut value = callee.obj.as_ref();
+    while let Expr::Paren(p) = value {
+        value = &p.expr;
+    }
+    Some((value, target))
+}
+
+/// Signedness and width of a fixed-size integer type.
+fn int_layout(of_type: &str) -> Option<(bool, u32)> {
+    Some(match of_type {
+        "u8" => (false, 8),
+        "i8" => (true, 8),
+        "u16" => (false, 16),
+        "i16" => (true, 16),
+        "u32" => (false, 32),
+        "i32" => (true, 32),
+        "u64" => (false, 64),
+        "i64" => (true, 64),
+        "u128" => (false, 128),
+        "i128" => (true, 128),
+        _ => return None,
+    })
+}
+
+/// Signedness and width of an integer type, counting `usize` and `isize` as 64 bits.
+fn int_range(of_type: &str) -> Option<(bool, u32)> {
+    match of_type {
+        "usize" => Some((false, 64)),
+        "isize" => Some((true, 64)),
+        _ => int_layout(of_type),
+    }
+}
+
+/// The value of an integer literal, `1000` or `1_000_000_000n`. Numbers above
+/// `Number.MAX_SAFE_INTEGER` are rejected, since TypeScript has already rounded them.
+pub fn int_literal_value(lit: &Lit) -> Result<u128, String> {
+    match lit {
+        Lit::Num(num) if num.value.fract() != 0.0 => {
+            Err(format!("`{}` is not an integer", num.value))
+        }
+        Lit::Num(num) if num.value > MAX_SAFE_INTEGER => {
+            let raw = num
+                .raw
+                .as_ref()
+                .map(|raw| raw.to_string())
+                .unwrap_or_else(|| num.value.to_string());
+            Err(format!(
+                "`{}` can't be represented exactly as a number, write it as a BigInt: `{}n`",
+                raw, raw
+            ))
+        }
+        Lit::Num(num) => Ok(num.value as u128),
+        Lit::BigInt(big_int) => big_int.value.to_string().parse::<u128>().map_err(|_| {
+            format!(
+                "`{}n` is out of range for every integer type",
+                big_int.value
+            )
+        }),
+        _ => Err("expected an integer literal".to_string()),
+    }
+}
+
+/// Whether the integer `-magnitude` (or `magnitude`) is a value of `of_type`.
+pub fn literal_fits(negative: bool, magnitude: u128, of_type: &str) -> bool {
+    let Some((signed, bits)) = int_range(of_type) else {
+        return true;
+    };
+    match (signed, negative) {
+        (false, true) => magnitude == 0,
+        (false, false) => bits == 128 || magnitude < 1 << bits,
+        (true, true) => magnitude <= 1 << (bits - 1),
+        (true, false) => magnitude < 1 << (bits - 1),
+    }
+}
+
+/// The values of an integer type, for diagnostics.
+fn describe_range(of_type: &str) -> Option<String> {
+    let (signed, bits) = int_range(of_type)?;
+    Some(match (signed, bits) {
+        (false, 128) => format!("`{}` holds values up to {}", of_type, u128::MAX),
+        (false, bits) => format!("`{}` holds values up to {}", of_type, (1u128 << bits) - 1),
+        (true, bits) => format!(
+            "`{}` holds values from -{} to {}",
+            of_type,
+            1u128 << (bits - 1),
+            (1u128 << (bits - 1)) - 1
+        ),
+    })
+}
+
+fn is_int(of_type: &str) -> bool {
+    of_type == INTEGER || of_type == "usize" || of_type == "isize" || int_layout(of_type).is_some()
+}
+
+/// Whether every value of `from` fits in `to`, i.e. Rust implements `From<from> for to`.
+pub fn is_lossless_cast(from: &str, to: &str) -> bool {
+    if from == to {
+        return true;
+    }
+    match (int_layout(from), int_layout(to)) {
+        (Some((from_signed, from_bits)), Some((to_signed, to_bits))) => {
+            (from_signed == to_signed && to_bits >= from_bits)
+                || (!from_signed && to_signed && to_bits > from_bits)
+        }
+        _ => false,
+    }
+}
+
+/// The method helper `a.add(b)`, `a.lt(b)`, ... as the operator it stands for.
+fn helper_binary_op(method: &str) -> Option<BinaryOp> {
+    Some(match method {
+        "add" => BinaryOp::Add,
+        "sub" => BinaryOp::Sub,
+        "mul" => BinaryOp::Mul,
+        "div" => BinaryOp::Div,
+        "mod" => BinaryOp::Mod,
+        "eq" => BinaryOp::EqEqEq,
+        "neq" => BinaryOp::NotEqEq,
+        "lt" => BinaryOp::Lt,
+        "lte" => BinaryOp::LtEq,
+        "gt" => BinaryOp::Gt,
+        "gte" => BinaryOp::GtEq,
+        _ => return None,
+    })
+}
+
+/// The sysvar read by `expr` if it is `Clock.get()`, `Rent.get()` or `EpochSchedule.get()`.
+fn sysvar_of(expr: &Expr) -> Option<&str> {
+    let callee = expr.as_call()?.callee.as_expr()?.as_member()?;
+    if callee.prop.as_ident()?.sym != "get" {
+        return None;
+    }
+    let sysvar = callee.obj.as_ident()?.sym.as_ref();
+    ["Clock", "Rent", "EpochSchedule"]
+        .contains(&sysvar)
+        .then_some(sysvar)
+}
+
+/// Type of a sysvar field, or of the value returned by a sysvar method.
+fn sysvar_member_type(sysvar: &str, member: &str) -> Option<&'static str> {
+    Some(match (sysvar, member) {
+        ("Clock", "slot" | "epoch" | "leaderScheduleEpoch") => "u64",
+        ("Clock", "epochStartTimestamp" | "unixTimestamp") => "i64",
+        ("Rent", "lamportsPerByteYear" | "minimumBalance") => "u64",
+        ("Rent", "burnPercent") => "u8",
+        ("EpochSchedule", "warmup") => "Boolean",
+        ("EpochSchedule", _) => "u64",
+        _ => return None,
+    })
+}
+
+/// Whether `expr` is `Context.remainingAccounts`.
+fn is_remaining_accounts(expr: &Expr) -> bool {
+    expr.as_member().is_some_and(|m| {
+        m.obj.as_ident().is_some_and(|o| o.sym == "Context")
+            && m.prop
+                .as_ident()
+                .is_some_and(|p| p.sym == "remainingAccounts")
+    })
+}
+
+fn assign_target(left: &PatOrExpr) -> Option<&Expr> {
+    match left {
+        PatOrExpr::Expr(e) => Some(e),
+        PatOrExpr::Pat(p) => p.as_expr().map(|e| e.as_ref()),
+    }
+}
+
+#[derive(Debug)]
+pub struct TypeError {
+    pub span: Span,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+/// Infers the types of the expressions in the instructions of the program class, from the
+/// instruction parameters, the fields of custom accounts and argument structs, and the program
+/// constants. Mismatches are collected in `errors`, and the type converted by every cast call
+/// (`x.toU64()`, ...) is recorded in `casts`, so it can be lowered to `From` or `TryFrom`.
+pub struct TypeChecker<'a> {
+    program_name: String,
+    accounts: &'a HashMap<String, ProgramAccount>,
+    structs: &'a [ProgramStruct],
+    constants: Vec<ProgramConstant>,
+    scope: HashMap<String, String>, // variable name : type
+    pub errors: Vec<TypeError>,
+    pub casts: HashMap<Span, String>, // span of the cast call : type of its value
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(
+        class: &ClassExpr,
+        accounts: &'a HashMap<String, ProgramAccount>,
+        structs: &'a [ProgramStruct],
+    ) -> Result<Self> {
+        let program_name = class
+            .ident
+            .as_ref()
+            .map(|i| i.sym.to_string())
+            .unwrap_or_default();
+        let constants = class
+            .class
+            .body
+            .iter()
+            .filter_map(|c| c.as_class_prop())
+            .filter(|c| c.is_static && c.key.as_ident().is_some_and(|k| k.sym != "PROGRAM_ID"))
+            .map(ProgramConstant::from_class_prop)
+            .collect::<Result<Vec<ProgramConstant>>>()?;
+        Ok(Self {
+            program_name,
+            accounts,
+            structs,
+            constants,
+            scope: HashMap::new(),
+            errors: vec![],
+            casts: HashMap::new(),
+        })
+    }
+
+    pub fn check_class(&mut self, class: &ClassExpr) {
+        for prop in class.class.body.iter().filter_map(|c| c.as_class_prop()) {
+            let constant = self
+                .constants
+                .iter()
+                .find(|c| prop.key.as_ident().is_some_and(|k| k.sym == *c.name))
+                .map(|c| c.of_type.clone());
+            if let (Some(of_type), Some(value)) = (constant, &prop.value) {
+                self.check_literal(value, &of_type);
+            }
+        }
+        for method in class.class.body.iter().filter_map(|c| c.as_method()) {
+            self.scope.clear();
+            for param in method.function.params.iter() {
+                let Some(BindingIdent {
+                    id,
+                    type_ann: Some(type_ann),
+                }) = param.pat.as_ident()
+                else {
+                    continue;
+                };
+                if let Ok((of_type, _, optional)) = extract_ts_type(type_ann.clone()) {
+                    // `name?: Type` is `Option<Type>` until `if (name)` binds it
+                    let of_type = match optional || id.optional {
+                        true => format!("Option<{}>", of_type),
+                        false => of_type,
+                    };
+                    self.scope.insert(id.sym.to_string(), of_type);
+                }
+            }
+            if let Some(body) = &method.function.body {
+                body.stmts.iter().for_each(|stmt| self.check_stmt(stmt));
+            }
+        }
+    }
+
+    /// Prints the collected errors with the TypeScript source they point to.
+    pub fn report(&self, cm: &Lrc<SourceMap>) {
+        let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
+        for error in self.errors.iter() {
+            let mut diagnostic = handler.struct_span_err(error.span, &error.message);
+            if let Some(help) = &error.help {
+                diagnostic.help(help);
+            }
+            diagnostic.emit();
+        }
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(TypeError {
+            span,
+            message,
+            help: None,
+        });
+    }
+
+    /// Runs `check` with `name` bound to `of_type`, for the variable of a loop or the optional
+    /// parameter an `if` checks.
+    fn with_binding(&mut self, name: &str, of_type: Option<String>, check: impl FnOnce(&mut Self)) {
+        let shadowed = match of_type {
+            Some(of_type) => self.scope.insert(name.to_string(), of_type),
+            None => self.scope.remove(name),
+        };
+        check(self);
+        match shadowed {
+            Some(of_type) => self.scope.insert(name.to_string(), of_type),
+            None => self.scope.remove(name),
+        };
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(e) => {
+                self.infer(&e.expr);
+            }
+            Stmt::Block(b) => b.stmts.iter().for_each(|stmt| self.check_stmt(stmt)),
+            Stmt::If(i) => {
+                // `if (x)` on an optional parameter checks that it was passed, and binds it
+                let bound = i.test.as_ident().and_then(|test| {
+                    let of_type = self.scope.get(test.sym.as_ref())?;
+                    let of_type = of_type.strip_prefix("Option<")?.strip_suffix('>')?;
+                    Some((test.sym.to_string(), of_type.to_string()))
+                });
+                match bound {
+                    Some((name, of_type)) => self
+                        .with_binding(&name, Some(of_type), |checker| checker.check_stmt(&i.cons)),
+                    None => {
+                        self.infer(&i.test);
+                        self.check_stmt(&i.cons);
+                    }
+                }
+                if let Some(alt) = &i.alt {
+                    self.check_stmt(alt);
+                }
+            }
+            Stmt::For(f) => {
+                let Some(VarDeclOrExpr::VarDecl(v)) = &f.init else {
+                    return self.check_stmt(&f.body);
+                };
+                let Some(counter) = v.decls.first().and_then(|d| d.name.as_ident()) else {
+                    return self.check_stmt(&f.body);
+                };
+                // the counter takes the type of the bound, integer literals count in `u64`
+                let bound = f
+                    .test
+                    .as_deref()
+                    .and_then(|t| t.as_bin())
+                    .map(|t| t.right.as_ref());
+                let of_type = match bound.and_then(|b| self.infer(b)) {
+                    Some(of_type) if of_type == INTEGER => Some("u64".to_string()),
+                    of_type => of_type,
+                };
+                self.with_binding(counter.id.sym.as_ref(), of_type, |checker| {
+                    checker.check_stmt(&f.body)
+                });
+            }
+            Stmt::ForOf(f) => {
+                let ForHead::VarDecl(v) = &f.left else {
+                    return self.check_stmt(&f.body);
+                };
+                let Some(item) = v.decls.first().and_then(|d| d.name.as_ident()) else {
+                    return self.check_stmt(&f.body);
+                };
+                let of_type = match is_remaining_accounts(&f.right) {
+                    true => Some("AccountInfo".to_string()),
+                    false => self.infer(&f.right).and_then(|of_type| {
+                        of_type
+                            .strip_prefix("Vec<")
+                            .and_then(|t| t.strip_suffix('>'))
+                            .map(|t| t.to_string())
+                    }),
+                };
+                self.with_binding(item.id.sym.as_ref(), of_type, |checker| {
+                    checker.check_stmt(&f.body)
+                });
+            }
+            Stmt::Decl(Decl::Var(v)) => {
+                for decl in v.decls.iter() {
+                    let Some(init) = decl.init.as_deref() else {
+                        continue;
+                    };
+                    self.infer(init);
+                    // `const [pda, bump] = Pubkey.findProgramAddress(...)`
+                    if find_program_address_call(init).is_none() {
+                        continue;
+                    }
+                    let names = decl.name.as_array().map(|a| a.elems.as_slice());
+                    for (elem, of_type) in names.unwrap_or_default().iter().zip(["Pubkey", "u8"]) {
+                        if let Some(Pat::Ident(b)) = elem {
+                            self.scope.insert(b.id.sym.to_string(), of_type.to_string());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Unifies the types of the operands of `op`, returning the type both take.
+    fn unify(&self, left: &str, right: &str) -> Option<String> {
+        match (left, right) {
+            _ if left == right => Some(left.to_string()),
+            (INTEGER, other) | (other, INTEGER) if is_int(other) => Some(other.to_string()),
+            _ => None,
+        }
+    }
+
+    fn check_binary(
+        &mut self,
+        span: Span,
+        op: BinaryOp,
+        left: Option<String>,
+        right: Option<String>,
+    ) -> Option<String> {
+        use BinaryOp::*;
+        match op {
+            LogicalAnd | LogicalOr => {
+                for of_type in [&left, &right].into_iter().flatten() {
+                    if of_type != "Boolean" {
+                        self.error(
+                            span,
+                            format!("`{}` expects Boolean operands, found `{}`", op, of_type),
+                        );
+                    }
+                }
+                Some("Boolean".to_string())
+            }
+            EqEq | EqEqEq | NotEq | NotEqEq | Lt | LtEq | Gt | GtEq => {
+                if let (Some(left), Some(right)) = (&left, &right) {
+                    if self.unify(left, right).is_none() {
+                        self.errors.push(TypeError {
+                            span,
+                            message: format!(
+                                "mismatched types: cannot compare `{}` with `{}`",
+                                left, right
+                            ),
+                            help: self.conversion_help(left, right),
+                        });
+                    }
+                }
+                Some("Boolean".to_string())
+            }
+            LShift | RShift => {
+                for of_type in [&left, &right].into_iter().flatten() {
+                    if !is_int(of_type) {
+                        self.error(
+                            span,
+                            format!("`{}` expects integer operands, found `{}`", op, of_type),
+                        );
+                    }
+                }
+                left.filter(|l| is_int(l))
+            }
+            Add | Sub | Mul | Div | Mod | BitAnd | BitOr | BitXor => {
+                // `&`, `|` and `^` also combine Booleans
+                let is_bitwise = matches!(op, BitAnd | BitOr | BitXor);
+                for of_type in [&left, &right].into_iter().flatten() {
+                    let is_valid = is_int(of_type) || (is_bitwise && of_type == "Boolean");
+                    if !is_valid {
+                        self.error(
+                            span,
+                            format!("`{}` expects integer operands, found `{}`", op, of_type),
+                        );
+                        return None;
+                    }
+                }
+                let (left, right) = (left?, right?);
+                let unified = self.unify(&left, &right);
+                if unified.is_none() {
+                    self.errors.push(TypeError {
+                        span,
+                        message: format!(
+                            "mismatched types: cannot apply `{}` to `{}` and `{}`",
+                            op, left, right
+                        ),
+                        help: self.conversion_help(&left, &right),
+                    });
+                }
+                unified
+            }
+            _ => None,
+        }
+    }
+
+    /// Suggests the cast that makes two different integer types match.
+    fn conversion_help(&self, expected: &str, found: &str) -> Option<String> {
+        if !is_int(expected) || !is_int(found) || expected == INTEGER {
+            return None;
+        }
+        let method = format!("to{}", expected.to_case(Case::Pascal));
+        cast_target(&method).map(|_| format!("convert the value with `.{}()`", method))
+    }
+
+    /// Checks that an integer literal, possibly negated, is a value of `of_type`.
+    fn check_literal(&mut self, expr: &Expr, of_type: &str) {
+        let (negative, literal) = match expr {
+            Expr::Paren(p) => return self.check_literal(&p.expr, of_type),
+            Expr::Unary(u) if u.op == UnaryOp::Minus => (true, u.arg.as_ref()),
+            literal => (false, literal),
+        };
+        let Some(Ok(magnitude)) = literal.as_lit().map(int_literal_value) else {
+            return;
+        };
+        if !literal_fits(negative, magnitude, of_type) {
+            let sign = if negative { "-" } else { "" };
+            self.errors.push(TypeError {
+                span: expr.span(),
+                message: format!(
+                    "literal `{}{}` is out of range for `{}`",
+                    sign, magnitude, of_type
+                ),
+                help: describe_range(of_type),
+            });
+        }
+    }
+
+    /// Checks that the literal operands of `op` fit the type of the other operand.
+    fn check_literal_operands(
+        &mut self,
+        op: BinaryOp,
+        operands: [Option<&Expr>; 2],
+        left: &Option<String>,
+        right: &Option<String>,
+    ) {
+        use BinaryOp::*;
+        if matches!(op, LShift | RShift | LogicalAnd | LogicalOr) {
+            return;
+        }
+        let (Some(left), Some(right)) = (left, right) else {
+            return;
+        };
+        let Some(of_type) = self.unify(left, right).filter(|t| t != INTEGER) else {
+            return;
+        };
+        for operand in operands.into_iter().flatten() {
+            self.check_literal(operand, &of_type);
+        }
+    }
+
+    /// Checks that `value`, of type `found`, can be stored where `expected` is.
+    fn check_assignable(&mut self, value: &Expr, expected: Option<String>, found: Option<String>) {
+        let (Some(expected), Some(found)) = (expected, found) else {
+            return;
+        };
+        if self.unify(&expected, &found).is_some() {
+            self.check_literal(value, &expected);
+        } else {
+            self.errors.push(TypeError {
+                span: value.span(),
+                message: format!(
+                    "mismatched types: expected `{}`, found `{}`",
+                    expected, found
+                ),
+                help: self.conversion_help(&expected, &found),
+            });
+        }
+    }
+
+    fn field_type(&self, of_type: &str, field: &str) -> Option<String> {
+        let fields = match self.accounts.get(of_type) {
+            Some(account) => &account.fields,
+            None => &self.structs.iter().find(|s| s.name == of_type)?.fields,
+        };
+        fields
+            .iter()
+            .find(|f| f.name == field)
+            .map(|f| f.of_type.clone())
+    }
+
+    fn is_account(&self, of_type: &str) -> bool {
+        STANDARD_ACCOUNT_TYPES.contains(&of_type) || self.accounts.contains_key(of_type)
+    }
+
+    fn member_type(&mut self, m: &MemberExpr) -> Option<String> {
+        if let MemberProp::Computed(index) = &m.prop {
+            let index_type = self.infer(&index.expr);
+            if is_remaining_accounts(&m.obj) {
+                return Some("AccountInfo".to_string());
+            }
+            if let Some(index_type) = index_type.filter(|t| !is_int(t)) {
+                self.error(
+                    index.span,
+                    format!("index must be an integer, found `{}`", index_type),
+                );
+            }
+            return self
+                .infer(&m.obj)?
+                .strip_prefix("Vec<")
+                .and_then(|t| t.strip_suffix('>'))
+                .map(|t| t.to_string());
+        }
+        let prop = m.prop.as_ident()?.sym.as_ref();
+        if let Some(sysvar) = sysvar_of(&m.obj) {
+            return sysvar_member_type(sysvar, prop).map(|t| t.to_string());
+        }
+        if m.obj
+            .as_ident()
+            .is_some_and(|o| o.sym == *self.program_name)
+        {
+            return self
+                .constants
+                .iter()
+                .find(|c| c.name == prop)
+                .map(|c| c.of_type.clone());
+        }
+        if is_remaining_accounts(&m.obj) {
+            return (prop == "length").then(|| "u64".to_string());
+        }
+        let obj_type = self.infer(&m.obj)?;
+        match prop {
+            "length"
+                if obj_type == "Str"
+                    || obj_type == "Uint8Array"
+                    || obj_type.starts_with("Vec<") =>
+            {
+                Some("u64".to_string())
+            }
+            "key" if self.is_account(&obj_type) => Some("Pubkey".to_string()),
+            // the balance, unless the account has a `lamports` field
+            "lamports"
+                if self.is_account(&obj_type) && self.field_type(&obj_type, prop).is_none() =>
+            {
+                Some("u64".to_string())
+            }
+            _ => {
+                let field_type = self.field_type(&obj_type, prop);
+                if field_type.is_none() && self.structs.iter().any(|s| s.name == obj_type) {
+                    self.error(
+                        m.prop.span(),
+                        format!("`{}` has no field `{}`", obj_type, prop),
+                    );
+                }
+                field_type
+            }
+        }
+    }
+
+    fn call_type(&mut self, c: &CallExpr) -> Option<String> {
+        let callee = c.callee.as_expr().and_then(|callee| callee.as_member());
+        let method = callee
+            .and_then(|m| m.prop.as_ident())
+            .map(|p| p.sym.as_ref());
+        let (Some(callee), Some(method)) = (callee, method) else {
+            c.args.iter().for_each(|arg| {
+                self.infer(&arg.expr);
+            });
+            return None;
+        };
+        if let Some(sysvar) = sysvar_of(&callee.obj) {
+            c.args.iter().for_each(|arg| {
+                self.infer(&arg.expr);
+            });
+            return sysvar_member_type(sysvar, method).map(|t| t.to_string());
+        }
+        let obj_type = self.infer(&callee.obj);
+        let arg_types: Vec<Option<String>> =
+            c.args.iter().map(|arg| self.infer(&arg.expr)).collect();
+        let first_arg = arg_types.first().cloned().flatten();
+        if let Some(target) = cast_target(method) {
+            match obj_type {
+                Some(from) if !is_int(&from) => {
+                    self.error(
+                        c.span,
+                        format!("`{}` converts integers, found `{}`", method, from),
+                    );
+                }
+                Some(from) if from != INTEGER => {
+                    self.casts.insert(c.span, from);
+                }
+                _ => {}
+            }
+            return Some(target.to_string());
+        }
+        if method == "equals" {
+            for of_type in [&obj_type, &first_arg].into_iter().flatten() {
+                if of_type != "Pubkey" {
+                    self.error(
+                        c.span,
+                        format!("`equals` compares pubkeys, found `{}`", of_type),
+                    );
+                }
+            }
+            return Some("Boolean".to_string());
+        }
+        if let Some(op) = helper_binary_op(method) {
+            let arg = c.args.first().map(|arg| arg.expr.as_ref());
+            self.check_literal_operands(op, [Some(&callee.obj), arg], &obj_type, &first_arg);
+            return self.check_binary(c.span, op, obj_type, first_arg);
+        }
+        let elem_type = obj_type
+            .as_deref()
+            .and_then(|t| t.strip_prefix("Vec<"))
+            .and_then(|t| t.strip_suffix('>'))
+            .map(|t| t.to_string());
+        match method {
+            "getBump" => Some("u8".to_string()),
+            "addLamports" | "subLamports" => {
+                if let Some(arg) = c.args.first() {
+                    self.check_assignable(&arg.expr, Some("u64".to_string()), first_arg);
+                }
+                None
+            }
+            "push" if elem_type.is_some() => {
+                if let Some(arg) = c.args.first() {
+                    self.check_assignable(&arg.expr, elem_type, first_arg);
+                }
+                None
+            }
+            "pop" => elem_type,
+            "includes" => Some("Boolean".to_string()),
+            "concat" if obj_type.as_deref() == Some("Str") => {
+                if let Some(arg) = c.args.first() {
+                    self.check_assignable(&arg.expr, obj_type.clone(), first_arg);
+                }
+                obj_type
+            }
+            _ => None,
+        }
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Lit(lit @ (Lit::Num(_) | Lit::BigInt(_))) => {
+                if let Err(message) = int_literal_value(lit) {
+                    self.error(lit.span(), message);
+                }
+                Some(INTEGER.to_string())
+            }
+            Expr::Lit(Lit::Bool(_)) => Some("Boolean".to_string()),
+            Expr::Lit(Lit::Str(_)) => Some("Str".to_string()),
+            Expr::Paren(p) => self.infer(&p.expr),
+            Expr::Ident(i) => {
+                let of_type = self.scope.get(i.sym.as_ref()).cloned()?;
+                let Some(base_type) = of_type
+                    .strip_prefix("Option<")
+                    .and_then(|t| t.strip_suffix('>'))
+                else {
+                    return Some(of_type);
+                };
+                // optional accounts are rejected with the rest of their uses when lowering
+                if !self.is_account(base_type) {
+                    self.error(
+                        i.span,
+                        format!(
+                            "optional argument `{}` can only be used inside `if ({}) {{ ... }}`",
+                            i.sym, i.sym
+                        ),
+                    );
+                }
+                None
+            }
+            Expr::Member(m) => self.member_type(m),
+            Expr::Unary(u) => {
+                let arg = self.infer(&u.arg);
+                let (expected, valid) = match (u.op, arg.as_deref()) {
+                    (_, None) => return None,
+                    (UnaryOp::Bang, Some(arg)) => ("a Boolean", arg == "Boolean"),
+                    (UnaryOp::Tilde, Some(arg)) => ("an integer", is_int(arg)),
+                    (UnaryOp::Minus, Some(arg)) => (
+                        "a signed integer",
+                        arg == INTEGER
+                            || arg == "isize"
+                            || int_layout(arg).is_some_and(|(signed, _)| signed),
+                    ),
+                    _ => return None,
+                };
+                if !valid {
+                    self.error(
+                        u.span,
+                        format!(
+                            "`{}` expects {}, found `{}`",
+                            u.op,
+                            expected,
+                            arg.as_deref().unwrap_or_default()
+                        ),
+                    );
+                }
+                match u.op {
+                    UnaryOp::Bang => Some("Boolean".to_string()),
+                    _ => arg,
+                }
+            }
+            Expr::Bin(b) => {
+                let left = self.infer(&b.left);
+                let right = self.infer(&b.right);
+                self.check_literal_operands(b.op, [Some(&b.left), Some(&b.right)], &left, &right);
+                self.check_binary(b.span, b.op, left, right)
+            }
+            Expr::Assign(a) => {
+                let left = assign_target(&a.left).and_then(|left| self.infer(left));
+                let right = self.infer(&a.right);
+                match a.op.to_update() {
+                    // `a op= b` must be valid as `a = a op b`
+                    Some(op) => {
+                        let result = self.check_binary(a.span, op, left.clone(), right);
+                        if matches!(op, BinaryOp::LShift | BinaryOp::RShift) {
+                            return left;
+                        }
+                        self.check_assignable(&a.right, left.clone(), result);
+                    }
+                    None => self.check_assignable(&a.right, left.clone(), right),
+                }
+                left
+            }
+            Expr::Call(c) => self.call_type(c),
+            Expr::New(n) => {
+                n.args.iter().flatten().for_each(|arg| {
+                    self.infer(&arg.expr);
+                });
+                if n.callee.as_ident().is_some_and(|i| i.sym == "Pubkey") {
+                    return Some("Pubkey".to_string());
+                }
+                // `new i64(0)`
+                let of_type = n
+                    .callee
+                    .as_ident()
+                    .map(|i| i.sym.to_string())
+                    .filter(|of_type| int_layout(of_type).is_some())?;
+                if let Some(value) = n.args.iter().flatten().next() {
+                    self.check_literal(&value.expr, &of_type);
+                }
+                Some(of_type)
+            }
+            Expr::Array(a) => {
+                a.elems.iter().flatten().for_each(|elem| {
+                    self.infer(&elem.expr);
+                });
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transpiler::{parse_source, transpile_source};
+
+    const VAULT: &str = r#"
+        export interface Vault extends Account {
+          admin: Pubkey;
+          amount: u64;
+          offset: i64;
+          total: u128;
+          bump: u8;
+          locked: Boolean;
+        }
+        export interface Params {
+          price: u64;
+        }
+    "#;
+
+    /// The literal written as the expression statement `src`.
+    fn lit(src: &str) -> Lit {
+        let (module, _) = parse_source(&format!("{};", src)).unwrap();
+        match &module.body[0] {
+            ModuleItem::Stmt(Stmt::Expr(e)) => e.expr.as_lit().unwrap().clone(),
+            item => panic!("expected a literal, found {:?}", item),
+        }
+    }
+
+    /// Type checks `body` as an instruction taking a `Vault` and an optional `tip`, and passes the checker to `inspect`.
+    fn check<T>(body: &str, inspect: impl FnOnce(TypeChecker) -> T) -> T {
+        let src = format!(
+            r#"export default class TestProgram {{
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+              run(vault: Vault, price: u64, params: Params, tip?: u64) {{ {} }}
+            }}
+            {}"#,
+            body, VAULT
+        );
+        let (module, _) = parse_source(&src).unwrap();
+        let mut accounts = HashMap::new();
+        let mut structs = vec![];
+        let mut class = None;
+        for item in module.body {
+            match item {
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl {
+                    decl: Decl::TsInterface(interface),
+                    ..
+                })) if interface.extends.is_empty() => {
+                    structs.push(ProgramStruct::from_ts_expr(*interface).unwrap());
+                }
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl {
+                    decl: Decl::TsInterface(interface),
+                    ..
+                })) => {
+                    let account = ProgramAccount::from_ts_expr(*interface);
+                    accounts.insert(account.name.clone(), account);
+                }
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDefaultDecl(decl)) => {
+                    class = decl.decl.class()
+                }
+                _ => {}
+            }
+        }
+        let class = class.unwrap();
+        let mut checker = TypeChecker::new(&class, &accounts, &structs).unwrap();
+        checker.check_class(&class);
+        inspect(checker)
+    }
+
+    /// The single diagnostic of `body`, with its help.
+    fn error(body: &str) -> (String, Option<String>) {
+        let mut errors = check(body, |checker| checker.errors);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        let error = errors.remove(0);
+        (error.message, error.help)
+    }
+
+    #[test]
+    fn integer_literal_values() {
+        assert_eq!(int_literal_value(&lit("255")), Ok(255));
+        assert_eq!(int_literal_value(&lit("1_000_000")), Ok(1_000_000));
+        assert_eq!(
+            int_literal_value(&lit("9007199254740991")),
+            Ok(9007199254740991)
+        );
+        assert_eq!(
+            int_literal_value(&lit("1.5")),
+            Err("`1.5` is not an integer".to_string())
+        );
+        assert_eq!(
+            int_literal_value(&lit("9007199254740993")),
+            Err("`9007199254740993` can't be represented exactly as a number, write it as a BigInt: `9007199254740993n`".to_string())
+        );
+    }
+
+    #[test]
+    fn bigint_literal_values() {
+        assert_eq!(int_literal_value(&lit("1_000_000_000n")), Ok(1_000_000_000));
+        assert_eq!(
+            int_literal_value(&lit("9007199254740993n")),
+            Ok(9007199254740993)
+        );
+        assert_eq!(
+            int_literal_value(&lit("340282366920938463463374607431768211455n")),
+            Ok(u128::MAX)
+        );
+        assert_eq!(
+            int_literal_value(&lit("340282366920938463463374607431768211456n")),
+            Err(
+                "`340282366920938463463374607431768211456n` is out of range for every integer type"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn literals_fit_their_type() {
+        assert!(literal_fits(false, 255, "u8"));
+        assert!(!literal_fits(false, 256, "u8"));
+        assert!(!literal_fits(true, 1, "u8"));
+        assert!(literal_fits(true, 0, "u8"));
+        assert!(literal_fits(false, i64::MAX as u128, "i64"));
+        assert!(!literal_fits(false, 1 << 63, "i64"));
+        assert!(literal_fits(true, 1 << 63, "i64"));
+        assert!(!literal_fits(true, (1 << 63) + 1, "i64"));
+        assert!(literal_fits(false, u128::MAX, "u128"));
+        assert!(!literal_fits(true, 1, "u128"));
+        assert!(literal_fits(false, u64::MAX as u128, "usize"));
+        assert!(literal_fits(false, u128::MAX, "Pubkey"));
+    }
+
+    #[test]
+    fn lossless_casts() {
+        assert!(is_lossless_cast("u8", "u64"));
+        assert!(is_lossless_cast("u64", "u128"));
+        assert!(is_lossless_cast("u32", "i64"));
+        assert!(is_lossless_cast("i64", "i64"));
+        assert!(!is_lossless_cast("u64", "u8"));
+        assert!(!is_lossless_cast("u64", "i64"));
+        assert!(!is_lossless_cast("i8", "u16"));
+        assert!(!is_lossless_cast("u64", "usize"));
+    }
+
+    #[test]
+    fn unify_integer_literals_with_integer_types() {
+        check("", |checker| {
+            assert_eq!(checker.unify("u64", "u64"), Some("u64".to_string()));
+            assert_eq!(checker.unify(INTEGER, "u8"), Some("u8".to_string()));
+            assert_eq!(checker.unify("i64", INTEGER), Some("i64".to_string()));
+            assert_eq!(checker.unify(INTEGER, INTEGER), Some(INTEGER.to_string()));
+            assert_eq!(checker.unify("u8", "u64"), None);
+            assert_eq!(checker.unify(INTEGER, "Pubkey"), None);
+        });
+    }
+
+    #[test]
+    fn binary_operand_types() {
+        let (u64_, u8_, boolean) = (
+            Some("u64".to_string()),
+            Some("u8".to_string()),
+            Some("Boolean".to_string()),
+        );
+        let span = swc_common::DUMMY_SP;
+        check("", |mut checker| {
+            assert_eq!(
+                checker.check_binary(span, BinaryOp::Add, u64_.clone(), Some(INTEGER.to_string())),
+                u64_
+            );
+            assert_eq!(
+                checker.check_binary(span, BinaryOp::Lt, u64_.clone(), u64_.clone()),
+                boolean
+            );
+            assert_eq!(
+                checker.check_binary(span, BinaryOp::LShift, u64_.clone(), u8_.clone()),
+                u64_
+            );
+            assert_eq!(
+                checker.check_binary(span, BinaryOp::BitAnd, boolean.clone(), boolean.clone()),
+                boolean
+            );
+            assert!(checker.errors.is_empty());
+            assert_eq!(
+                checker.check_binary(span, BinaryOp::Add, u64_.clone(), u8_.clone()),
+                None
+            );
+            assert_eq!(checker.errors.len(), 1);
+        });
+    }
+
+    #[test]
+    fn casts_pick_from_or_try_from() {
+        // the checker records the type each cast converts from
+        check(
+            "vault.amount = vault.bump.toU64(); vault.bump = vault.amount.toU8();",
+            |checker| {
+                assert!(checker.errors.is_empty());
+                let mut casts: Vec<&String> = checker.casts.values().collect();
+                casts.sort();
+                assert_eq!(casts, ["u64", "u8"]);
+            },
+        );
+
+        let rs = transpile_source(&format!(
+            r#"import {{ Account, Boolean, Pubkey, u8, u64, i64, u128 }} from "@solanaturbine/poseidon";
+            export default class TestProgram {{
+              static PROGRAM_ID = new Pubkey("11111111111111111111111111111111");
+              run(vault: Vault) {{
+                vault.amount = vault.bump.toU64();
+                vault.bump = vault.amount.toU8();
+              }}
+            }}
+            {}"#,
+            VAULT
+        ))
+        .unwrap();
+        assert!(rs.contains("ctx.accounts.vault.amount = u64::from(ctx.accounts.vault.bump);"));
+        assert!(rs.contains("u8::try_from(ctx.accounts.vault.amount)"));
+        assert!(rs.contains(".map_err(|_| ProgramError::ArithmeticOverflow)?;"));
+    }
+
+    #[test]
+    fn literal_range_diagnostics() {
+        let body = "vault.bump = 255; vault.offset = -9223372036854775808n; vault.total = 340282366920938463463374607431768211455n;";
+        assert!(check(body, |checker| checker.errors.is_empty()));
+        assert_eq!(
+            error("vault.bump = 256;"),
+            (
+                "literal `256` is out of range for `u8`".to_string(),
+                Some("`u8` holds values up to 255".to_string())
+            )
+        );
+        assert_eq!(
+            error("vault.amount = -1;"),
+            (
+                "literal `-1` is out of range for `u64`".to_string(),
+                Some("`u64` holds values up to 18446744073709551615".to_string())
+            )
+        );
+        assert_eq!(
+            error("vault.offset = -9223372036854775809n;"),
+            (
+                "literal `-9223372036854775809` is out of range for `i64`".to_string(),
+                Some(
+                    "`i64` holds values from -9223372036854775808 to 9223372036854775807"
+                        .to_string()
+                )
+            )
+        );
+        assert_eq!(
+            error("vault.amount = 9007199254740993;").0,
+            "`9007199254740993` can't be represented exactly as a number, write it as a BigInt: `9007199254740993n`"
+        );
+        assert_eq!(
+            error("vault.locked = vault.bump > 300;").0,
+            "literal `300` is out of range for `u8`"
+        );
+    }
+
+    #[test]
+    fn mismatch_diagnostics() {
+        assert_eq!(
+            error("vault.amount = vault.bump;"),
+            (
+                "mismatched types: expected `u64`, found `u8`".to_string(),
+                Some("convert the value with `.toU64()`".to_string())
+            )
+        );
+        assert_eq!(
+            error("vault.locked = vault.admin === vault.amount;"),
+            (
+                "mismatched types: cannot compare `Pubkey` with `u64`".to_string(),
+                None
+            )
+        );
+        assert_eq!(
+            error("vault.amount = price + vault.bump;"),
+            (
+                "mismatched types: cannot apply `+` to `u64` and `u8`".to_string(),
+                Some("convert the value with `.toU64()`".to_string())
+            )
+        );
+        assert_eq!(
+            error("vault.locked = vault.locked && vault.amount;").0,
+            "`&&` expects Boolean operands, found `u64`"
+        );
+        assert_eq!(
+            error("vault.amount = vault.amount << vault.admin;").0,
+            "`<<` expects integer operands, found `Pubkey`"
+        );
+        assert_eq!(
+            error("vault.amount = vault.amount * vault.locked;").0,
+            "`*` expects integer operands, found `Boolean`"
+        );
+        assert_eq!(
+            error("vault.amount = params.prise;").0,
+            "`Params` has no field `prise`"
+        );
+        assert_eq!(
+            error("vault.locked = !vault.amount;").0,
+            "`!` expects a Boolean, found `u64`"
+        );
+        assert_eq!(
+            error("vault.amount = -price;").0,
+            "`-` expects a signed integer, found `u64`"
+        );
+    }
+    #[test]
+    fn optional_parameters_are_bound_by_their_if() {
+        check("if (tip) { vault.amount = tip + 1; }", |checker| {
+            assert!(checker.errors.is_empty());
+            assert_eq!(checker.scope["tip"], "Option<u64>");
+        });
+        assert_eq!(
+            error("vault.amount = tip;").0,
+            "optional argument `tip` can only be used inside `if (tip) { ... }`"
+        );
+        assert_eq!(
+            error("if (tip) { vault.amount = tip; } else { vault.amount = tip; }").0,
+            "optional argument `tip` can only be used inside `if (tip) { ... }`"
+        );
+    }
+}